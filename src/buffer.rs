@@ -3,6 +3,7 @@ use std::cmp::{max, min};
 use std::collections::BTreeMap;
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use unicode_width::UnicodeWidthChar;
 
@@ -11,6 +12,7 @@ use crate::compiler::CompileResult;
 use crate::compiler::Compiler;
 use crate::config;
 use crate::config::types::keys;
+use crate::config::types::CommentConfig;
 use crate::core::Core;
 use crate::core::Cursor;
 use crate::core::CursorRange;
@@ -19,31 +21,27 @@ use crate::draw;
 use crate::draw::{styles, CharStyle, LinenumView, TermView};
 use crate::draw_cache::DrawCache;
 use crate::formatter;
+use crate::indent::{self, IndentRules};
 use crate::lsp::LSPClient;
+use crate::parenthesis;
+use crate::register::Registers;
 use crate::ropey_util::RopeExt;
+use crate::search::{self, SearchHistory};
 use crate::storage::Storage;
 use crate::syntax;
+use crate::test_case;
+use crate::undo_file;
 
-pub struct Yank {
-    pub insert_newline: bool,
-    pub content: String,
-}
-
-impl Default for Yank {
-    fn default() -> Self {
-        Yank {
-            insert_newline: false,
-            content: String::new(),
-        }
-    }
-}
-
-fn get_rows(s: &str, width: usize) -> usize {
+fn get_rows(s: &str, width: usize, tab_width: usize) -> usize {
     let mut x = 0;
     let mut y = 1;
 
     for c in s.chars() {
-        let w = c.width().unwrap_or(0);
+        let w = if c == '\t' {
+            tab_width - x % tab_width
+        } else {
+            c.width().unwrap_or(0)
+        };
         if x + w < width {
             x += w;
         } else {
@@ -63,15 +61,20 @@ enum ShowCursor {
 pub struct Buffer<'a> {
     storage: Option<Box<dyn Storage>>,
     pub core: Core,
-    pub search: Vec<char>,
+    search: String,
+    search_regex: Option<regex::Regex>,
+    pub search_history: SearchHistory,
     syntax_parent: &'a syntax::SyntaxParent,
     config: &'a config::ConfigWithDefault,
     syntax: syntax::Syntax<'a>,
     pub snippet: BTreeMap<String, String>,
-    pub yank: Yank,
+    pub registers: Registers,
+    // Selection of the last Visual mode for `'<,'>`
+    pub last_visual: Option<CursorRange>,
     last_save: Id,
     pub lsp: Option<LSPClient>,
     compiler: Option<Compiler<'a>>,
+    indent_rules: IndentRules,
     row_offset: usize,
     last_compiler_result: Option<CompileResult>,
     cache: DrawCache<'a>,
@@ -91,14 +94,21 @@ impl<'a> Buffer<'a> {
         let mut res = Self {
             storage: None,
             core: Core::default(),
-            search: Vec::new(),
+            search: String::new(),
+            search_regex: None,
+            search_history: SearchHistory::default(),
             cache: DrawCache::new(&syntax),
             syntax,
             snippet: BTreeMap::new(),
-            yank: Yank::default(),
+            registers: Registers::default(),
+            last_visual: None,
             last_save: Id::default(),
             lsp: None,
             compiler: config.get::<keys::Compiler>(None).map(Compiler::new),
+            indent_rules: config
+                .get::<keys::IndentRules>(None)
+                .cloned()
+                .unwrap_or_default(),
             row_offset: 0,
             last_compiler_result: None,
             syntax_parent,
@@ -142,6 +152,55 @@ impl<'a> Buffer<'a> {
         self.get_config::<keys::IndentWidth>().cloned().unwrap_or(4)
     }
 
+    pub fn comment(&self) -> Option<&'a CommentConfig> {
+        self.get_config::<keys::Comment>()
+    }
+
+    pub fn expand_tab(&self) -> bool {
+        self.get_config::<keys::ExpandTab>()
+            .cloned()
+            .unwrap_or(true)
+    }
+
+    pub fn auto_pairs(&self) -> &'a [(char, char)] {
+        self.get_config::<keys::AutoPairs>()
+            .map(Vec::as_slice)
+            .unwrap_or(&parenthesis::AUTO_PAIRS)
+    }
+
+    // Limits to run tests. `limits.toml` in the test directory overrides the config.
+    pub fn limits(&self) -> test_case::Limits {
+        let mut limits = test_case::Limits::default();
+        if let Some(&ms) = self.get_config::<keys::TimeLimit>() {
+            limits.time = Duration::from_millis(ms);
+        }
+        if let Some(&mb) = self.get_config::<keys::MemoryLimit>() {
+            limits.memory = test_case::megabytes(mb);
+        }
+        match self.path().and_then(test_case::test_dir) {
+            Some(dir) => limits.with_file(&dir),
+            None => limits,
+        }
+    }
+
+    // Name of the syntax like "Rust"
+    pub fn syntax_name(&self) -> &str {
+        &self.syntax.syntax.name
+    }
+
+    // Whitespace for an indent of `width` columns
+    pub fn indent_string(&self, width: usize) -> String {
+        indent::indent_string(width, self.indent_width(), self.expand_tab())
+    }
+
+    pub fn indent_rules(&self) -> &IndentRules {
+        &self.indent_rules
+    }
+
+    pub fn text_width(&self) -> usize {
+        self.get_config::<keys::TextWidth>().cloned().unwrap_or(80)
+    }
+
     pub fn restart_lsp(&mut self) {
         let ext = self
             .extension()
@@ -160,12 +219,33 @@ impl<'a> Buffer<'a> {
     }
 
     pub fn set_language(&mut self) {
+        if let Some(path) = self.path() {
+            let syntax_extension = self
+                .get_config::<keys::SyntaxExtension>()
+                .cloned()
+                .or_else(|| {
+                    path.extension()
+                        .or_else(|| path.file_name())
+                        .unwrap_or_default()
+                        .to_str()
+                        .map(String::from)
+                })
+                .unwrap_or_default();
+            self.set_syntax(&syntax_extension);
+        }
         self.compiler = self.get_config::<keys::Compiler>().map(Compiler::new);
+        self.indent_rules = self
+            .get_config::<keys::IndentRules>()
+            .cloned()
+            .unwrap_or_default();
         self.restart_lsp();
     }
 
     pub fn indent(&mut self) {
-        self.core.indent(self.indent_width());
+        let indent_width = self.indent_width();
+        let expand_tab = self.expand_tab();
+        self.core
+            .indent(&self.indent_rules, indent_width, expand_tab);
     }
 
     pub fn set_path(&mut self, path: PathBuf) {
@@ -175,21 +255,8 @@ impl<'a> Buffer<'a> {
 
     pub fn open<S: Storage + 'static>(&mut self, mut storage: S) {
         self.core = storage.load();
+        let _ = undo_file::load(storage.path(), &mut self.core);
         self.storage = Some(Box::new(storage));
-        let path = self.path().unwrap();
-
-        let syntax_extension = self
-            .get_config::<keys::SyntaxExtension>()
-            .cloned()
-            .or_else(|| {
-                path.extension()
-                    .or_else(|| path.file_name())
-                    .unwrap_or_default()
-                    .to_str()
-                    .map(String::from)
-            })
-            .unwrap_or_default();
-        self.set_syntax(&syntax_extension);
 
         self.row_offset = 0;
         self.last_save = self.core.buffer_changed();
@@ -206,6 +273,9 @@ impl<'a> Buffer<'a> {
             false
         };
         if saved {
+            if let Some(path) = self.path() {
+                let _ = undo_file::save(path, &self.core);
+            }
             self.compile(is_optimize);
         }
         saved
@@ -229,9 +299,19 @@ impl<'a> Buffer<'a> {
             let cols = cols - LinenumView::prefix_width(self.core.buffer().len_lines());
             let mut i = self.core.cursor().row + 1;
             let mut sum = 0;
-            while i > 0 && sum + get_rows(&Cow::from(self.core.buffer().l(i - 1)), cols) <= rows {
-                sum += get_rows(&Cow::from(self.core.buffer().l(i - 1)), cols);
-                i -= 1;
+            while i > 0 {
+                // A closed fold is one row
+                let (row, h) = if let Some((start, _)) = self.core.fold(i - 1) {
+                    (start, 1)
+                } else {
+                    let line = Cow::from(self.core.buffer().l(i - 1));
+                    (i - 1, get_rows(&line, cols, self.indent_width()))
+                };
+                if sum + h > rows {
+                    break;
+                }
+                sum += h;
+                i = row;
             }
             self.row_offset = max(i, self.row_offset);
         }
@@ -257,6 +337,32 @@ impl<'a> Buffer<'a> {
         self.row_offset = min(self.row_offset + 3, self.core.buffer().len_lines() - 1);
     }
 
+    pub fn search(&self) -> &str {
+        &self.search
+    }
+
+    pub fn set_search(&mut self, search: String) {
+        self.search_regex = search::build_regex(&search);
+        self.search = search;
+    }
+
+    // Move the cursor to the next match. Returns false if nothing matched.
+    pub fn search_next(&mut self, forward: bool) -> bool {
+        if let Some(regex) = self.search_regex.as_ref() {
+            let found = if forward {
+                search::find_next(self.core.buffer(), regex, self.core.cursor())
+            } else {
+                search::find_prev(self.core.buffer(), regex, self.core.cursor())
+            };
+            if let Some(cursor) = found {
+                self.core.push_jump();
+                self.core.set_cursor(cursor);
+                return true;
+            }
+        }
+        false
+    }
+
     pub fn format(&mut self) {
         let src = self.core.get_string();
         let formatter = self.config.get::<keys::Formatter>(self.path());
@@ -339,15 +445,114 @@ impl<'a> Buffer<'a> {
             .unwrap_or(false)
     }
 
+    fn refresh_cache(&mut self) {
+        if self.buffer_update != self.core.buffer_changed() {
+            self.buffer_update = self.core.buffer_changed();
+            self.cache.dirty_from(self.core.dirty_from);
+        }
+    }
+
+    // Whether typing at `cursor` is in a string or a comment
+    pub fn in_literal(&mut self, cursor: Cursor) -> bool {
+        self.refresh_cache();
+        self.cache.cache_line(self.core.buffer(), cursor.row);
+        self.cache.in_literal(cursor.row, cursor.col)
+    }
+
+    // Columns of brackets in `row` outside strings and comments
+    fn brackets(&mut self, row: usize) -> Vec<usize> {
+        self.cache.cache_line(self.core.buffer(), row);
+        self.cache
+            .get_brackets(row)
+            .map(<[usize]>::to_vec)
+            .unwrap_or_default()
+    }
+
+    // % of Vim. The first bracket at or after `cursor` in the line and its pair.
+    pub fn matching_bracket(&mut self, cursor: Cursor) -> Option<(Cursor, Cursor)> {
+        let rows = self.core.buffer().len_lines();
+        self.matching_bracket_within(cursor, rows)
+    }
+
+    fn matching_bracket_within(
+        &mut self,
+        cursor: Cursor,
+        max_rows: usize,
+    ) -> Option<(Cursor, Cursor)> {
+        self.refresh_cache();
+        if cursor.row >= self.core.buffer().len_lines() {
+            return None;
+        }
+        let col = self
+            .brackets(cursor.row)
+            .into_iter()
+            .find(|&c| c >= cursor.col)?;
+        let from = Cursor {
+            row: cursor.row,
+            col,
+        };
+        let c = self.core.char_at(from)?;
+        let (open, close, forward) =
+            parenthesis::PARENTHESIS_PAIRS.iter().find_map(|&(l, r)| {
+                if c == l {
+                    Some((l, r, true))
+                } else if c == r {
+                    Some((r, l, false))
+                } else {
+                    None
+                }
+            })?;
+
+        let rows: Vec<usize> = if forward {
+            (cursor.row..min(self.core.buffer().len_lines(), cursor.row + max_rows)).collect()
+        } else {
+            (cursor.row.saturating_sub(max_rows)..=cursor.row)
+                .rev()
+                .collect()
+        };
+        let mut depth = 0;
+        for row in rows {
+            let mut cols = self.brackets(row);
+            if forward {
+                cols.retain(|&c| row != from.row || c >= from.col);
+            } else {
+                cols.retain(|&c| row != from.row || c <= from.col);
+                cols.reverse();
+            }
+            for col in cols {
+                let to = Cursor { row, col };
+                let c = self.core.char_at(to)?;
+                if c == open {
+                    depth += 1;
+                } else if c == close {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some((from, to));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    // The pair to highlight when the cursor is on a bracket
+    fn matching_on_cursor(&mut self, max_rows: usize) -> Vec<Cursor> {
+        let cursor = self.core.cursor();
+        match self.matching_bracket_within(cursor, max_rows) {
+            Some((from, to)) if from == cursor => vec![from, to],
+            _ => Vec::new(),
+        }
+    }
+
     pub fn draw(&mut self, view: TermView) -> Option<Cursor> {
         self.poll_compile_message();
-        self.draw_with_selected(view, None)
+        self.draw_with_selected(view, &[])
     }
 
     pub fn draw_with_selected(
         &mut self,
         mut view: TermView,
-        selected: Option<CursorRange>,
+        selected: &[CursorRange],
     ) -> Option<Cursor> {
         match self.show_cursor_on_draw {
             ShowCursor::ShowMiddle => {
@@ -360,6 +565,8 @@ impl<'a> Buffer<'a> {
         }
         self.show_cursor_on_draw = ShowCursor::None;
         view.bg = self.syntax.theme.settings.background.map(Into::into);
+        view.tab_width = self.indent_width();
+        let matching = self.matching_on_cursor(view.height());
         let v = Vec::new();
         let compiler_outputs = self
             .last_compiler_result
@@ -374,29 +581,48 @@ impl<'a> Buffer<'a> {
         );
         let mut cursor = None;
 
-        if self.buffer_update != self.core.buffer_changed() {
-            self.buffer_update = self.core.buffer_changed();
-            self.cache.dirty_from(self.core.dirty_from);
-        }
-
         'outer: for i in self.row_offset..self.core.buffer().len_lines() {
+            if let Some((start, end)) = self.core.fold(i) {
+                if i != start && i != self.row_offset {
+                    continue;
+                }
+                let t = Cursor { row: i, col: 0 };
+                if (start..=end).contains(&self.core.cursor().row) {
+                    cursor = view.cursor();
+                }
+                let summary = format!(
+                    "+--{:>4} lines: {}",
+                    end - start + 1,
+                    Cow::from(self.core.buffer().l(start)).trim()
+                );
+                for c in summary.chars() {
+                    if view.cause_newline(c) {
+                        break;
+                    }
+                    if view.put(c, styles::UI, Some(t)).is_none() {
+                        break 'outer;
+                    }
+                }
+                if end + 1 < self.core.buffer().len_lines() {
+                    while !view.cause_newline(' ') {
+                        view.put(' ', styles::UI, Some(t));
+                    }
+                    view.newline_after_fold(end - i);
+                }
+                continue;
+            }
+
             self.cache.cache_line(self.core.buffer(), i);
             let line_ref = self.cache.get_line(i).unwrap();
             let mut line = Cow::Borrowed(line_ref);
 
             self.core.dirty_from = i;
 
-            if !self.search.is_empty() && line.len() >= self.search.len() {
-                for j in 0..=line.len() - self.search.len() {
-                    let m = self
-                        .search
-                        .iter()
-                        .zip(line[j..j + self.search.len()].iter())
-                        .all(|(c1, (c2, _))| c1 == c2);
-                    if m {
-                        for k in j..j + self.search.len() {
-                            line.to_mut()[k].1 = draw::styles::HIGHLIGHT;
-                        }
+            if let Some(regex) = self.search_regex.as_ref() {
+                let matches = search::find_in_line(regex, &Cow::from(self.core.buffer().l(i)));
+                for (l, r) in matches {
+                    for k in l..min(r, line.len()) {
+                        line.to_mut()[k].1 = draw::styles::HIGHLIGHT;
                     }
                 }
             }
@@ -409,11 +635,14 @@ impl<'a> Buffer<'a> {
                     style.modification = draw::CharModification::UnderLine;
                 }
 
-                let style = if selected.as_ref().map(|r| r.contains(t)) == Some(true) {
-                    styles::SELECTED
-                } else {
-                    style
-                };
+                let style =
+                    if selected.iter().any(|r| r.contains(t)) || self.core.cursors().contains(&t) {
+                        styles::SELECTED
+                    } else if matching.contains(&t) {
+                        styles::MATCHING
+                    } else {
+                        style
+                    };
 
                 if self.core.cursor() == t {
                     cursor = view.put(c, style, Some(t));
@@ -430,7 +659,9 @@ impl<'a> Buffer<'a> {
                 cursor = view.cursor();
             }
 
-            if self.core.buffer().l(i).len_chars() == 0 {
+            if self.core.cursors().contains(&t) {
+                view.put(' ', styles::SELECTED, Some(t));
+            } else if self.core.buffer().l(i).len_chars() == 0 {
                 if let Some(col) = self.syntax.theme.settings.background {
                     view.put(' ', CharStyle::bg(col.into()), Some(t));
                 } else {
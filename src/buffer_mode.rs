@@ -1,6 +1,8 @@
+use std::path::PathBuf;
+
 use crate::buffer::Buffer;
 use crate::draw;
-use crate::mode::{Mode, Normal, Transition};
+use crate::mode::{self, CommandPrefix, Mode, Normal, Transition};
 
 pub struct BufferMode<'a> {
     pub buf: Buffer<'a>,
@@ -8,6 +10,8 @@ pub struct BufferMode<'a> {
     is_recording: bool,
     dot_macro: Vec<termion::event::Event>,
     recording_macro: Vec<termion::event::Event>,
+    // Nest level of playing macros recorded by `q`
+    macro_depth: usize,
 }
 
 pub enum TabOperation {
@@ -16,6 +20,9 @@ pub enum TabOperation {
     NewTab,
     ChangeTab(usize),
     StartRmate,
+    SetGlobalMark(char),
+    JumpGlobalMark(char, bool),
+    OpenNewTab(PathBuf),
 }
 
 impl<'a> BufferMode<'a> {
@@ -26,10 +33,20 @@ impl<'a> BufferMode<'a> {
             is_recording: false,
             dot_macro: Vec::new(),
             recording_macro: Vec::new(),
+            macro_depth: 0,
         }
     }
 
+    // Stop recursive macros like `qaq@aq` at this depth
+    const MAX_MACRO_DEPTH: usize = 100;
+
     pub fn event(&mut self, event: termion::event::Event) -> TabOperation {
+        // Events played by macros should not be recorded twice
+        self.buf.registers.record(event.clone());
+        self.handle_event(event)
+    }
+
+    fn handle_event(&mut self, event: termion::event::Event) -> TabOperation {
         if self.is_recording {
             self.recording_macro.push(event.clone());
         }
@@ -41,9 +58,28 @@ impl<'a> BufferMode<'a> {
                 t.init(&mut self.buf);
                 self.mode = t;
             }
-            Transition::DoMacro => {
-                for event in self.dot_macro.clone() {
-                    self.event(event);
+            Transition::DoMacro(count) => {
+                let mut events = self.dot_macro.clone();
+                if let Some(count) = count {
+                    // Replace the count of the recorded command
+                    let (mut prefix, len) = CommandPrefix::parse(&events);
+                    prefix.count = Some(count);
+                    let prefix = prefix.events();
+                    let start = prefix.len();
+                    events.splice(..len, prefix);
+                    mode::remove_motion_count(&mut events, start);
+                }
+                for event in events {
+                    self.handle_event(event);
+                }
+            }
+            Transition::PlayMacro(events) => {
+                if self.macro_depth < Self::MAX_MACRO_DEPTH {
+                    self.macro_depth += 1;
+                    for event in events {
+                        self.handle_event(event);
+                    }
+                    self.macro_depth -= 1;
                 }
             }
             Transition::Return(s, is_commit_macro) => {
@@ -60,9 +96,10 @@ impl<'a> BufferMode<'a> {
                 t.init(&mut self.buf);
                 self.mode = t;
             }
-            Transition::RecordMacro(mut t) => {
+            Transition::RecordMacro(mut t, prefix) => {
                 self.is_recording = true;
                 self.recording_macro.clear();
+                self.recording_macro.extend(prefix.events());
                 self.recording_macro.push(event);
                 t.init(&mut self.buf);
                 self.mode = t;
@@ -79,11 +116,28 @@ impl<'a> BufferMode<'a> {
                 self.mode = Box::new(Normal::default());
                 return TabOperation::StartRmate;
             }
+            Transition::SetGlobalMark(c) => {
+                return TabOperation::SetGlobalMark(c);
+            }
+            Transition::JumpGlobalMark(c, linewise) => {
+                return TabOperation::JumpGlobalMark(c, linewise);
+            }
+            Transition::OpenNewTab(path) => {
+                return TabOperation::OpenNewTab(path);
+            }
             Transition::Nothing => {}
         }
         TabOperation::Nothing
     }
 
+    // Only opening a tab is supported
+    pub fn poll(&mut self) -> TabOperation {
+        match self.mode.poll(&mut self.buf) {
+            Transition::OpenNewTab(path) => TabOperation::OpenNewTab(path),
+            _ => TabOperation::Nothing,
+        }
+    }
+
     pub fn draw(&mut self, view: draw::TermView) -> draw::CursorState {
         self.mode.draw(&mut self.buf, view)
     }
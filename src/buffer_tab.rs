@@ -5,9 +5,11 @@ use crate::config::ConfigWithDefault;
 use crate::draw;
 use crate::draw::CharStyle;
 use crate::rmate::{start_server, RmateSave, RmateStorage};
+use crate::search::SearchHistory;
 use crate::storage::Storage;
 use crate::syntax::SyntaxParent;
 use std::cmp::min;
+use std::path::PathBuf;
 use std::sync::mpsc;
 use std::thread;
 use unicode_width::UnicodeWidthChar;
@@ -63,17 +65,23 @@ pub struct BufferTab<'a> {
 
 impl<'a> BufferTab<'a> {
     pub fn new(syntax_parent: &'a SyntaxParent, config: &'a ConfigWithDefault) -> Self {
-        Self {
+        let mut res = Self {
             syntax_parent,
             config,
-            buffers: vec![BufferMode::new(Buffer::new(syntax_parent, config))],
+            buffers: Vec::new(),
             index: 0,
             rmate: None,
-        }
+        };
+        res.buffers.push(res.new_buffer_mode());
+        res
     }
 
     fn new_buffer_mode(&self) -> BufferMode<'a> {
-        BufferMode::new(Buffer::new(self.syntax_parent, self.config))
+        let mut buf = Buffer::new(self.syntax_parent, self.config);
+        if let Some(path) = SearchHistory::default_path() {
+            buf.search_history = SearchHistory::with_path(path);
+        }
+        BufferMode::new(buf)
     }
 
     pub fn open<S: Storage + 'static>(&mut self, s: S) {
@@ -137,10 +145,8 @@ impl<'a> BufferTab<'a> {
                 }
             }
             TabOperation::NewTab => {
-                self.buffers.push(BufferMode::new(Buffer::new(
-                    self.syntax_parent,
-                    self.config,
-                )));
+                let buffer_mode = self.new_buffer_mode();
+                self.buffers.push(buffer_mode);
                 self.index = self.buffers.len() - 1;
             }
             TabOperation::ChangeTab(i) => {
@@ -155,12 +161,51 @@ impl<'a> BufferTab<'a> {
                 });
                 self.rmate = Some(rx);
             }
+            TabOperation::SetGlobalMark(c) => {
+                for (i, buffer_mode) in self.buffers.iter_mut().enumerate() {
+                    if i != self.index {
+                        buffer_mode.buf.core.remove_mark(c);
+                    }
+                }
+            }
+            TabOperation::JumpGlobalMark(c, linewise) => {
+                if let Some(i) = self
+                    .buffers
+                    .iter()
+                    .position(|b| b.buf.core.mark(c).is_some())
+                {
+                    self.index = i;
+                    let buf = &mut self.buffer_mode_mut().buf;
+                    if let Some(cursor) = buf.core.mark(c) {
+                        buf.core.set_cursor(cursor);
+                        if linewise {
+                            buf.core.first_non_blank();
+                        }
+                        buf.show_cursor();
+                    }
+                }
+            }
+            TabOperation::OpenNewTab(path) => self.open_tab(path),
             TabOperation::Nothing => {}
         }
 
         false
     }
 
+    // Switch to the tab of `path` or open it
+    fn open_tab(&mut self, path: PathBuf) {
+        if let Some(i) = self
+            .buffers
+            .iter()
+            .position(|b| b.buf.path() == Some(path.as_path()))
+        {
+            self.index = i;
+        } else {
+            self.open(path);
+            self.index = self.buffers.len() - 1;
+        }
+    }
+
     fn draw_tab_line(&self, width: usize) -> TabLine {
         const TITLE_LEN: usize = 5;
         let mut footer = TabLine::new(width);
@@ -237,6 +282,9 @@ impl<'a> BufferTab<'a> {
                 }
             }
         }
+        if let TabOperation::OpenNewTab(path) = self.buffer_mode_mut().poll() {
+            self.open_tab(path);
+        }
         let width = view.width();
 
         let cursor =
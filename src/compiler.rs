@@ -77,6 +77,17 @@ impl<'a> Compiler<'a> {
     pub fn is_compiling(&self) -> bool {
         self.worker.is_compiling()
     }
+    // Compile and block until it finishes
+    pub fn compile_wait(&self, path: PathBuf, is_optimize: bool) -> Option<CompileResult> {
+        self.compile(
+            path,
+            CompileId {
+                is_optimize,
+                ..Default::default()
+            },
+        );
+        self.recv_compile_result().map(|(_, res)| res)
+    }
 }
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
@@ -8,7 +8,10 @@ use typemap::Key;
 use crate::config::snippet::load_snippet;
 use crate::config::types::keys;
 use crate::config::types::Command;
+use crate::config::types::CommentConfig;
 use crate::config::types::CompilerConfig;
+use crate::config::types::IndentRulesConfig;
+use crate::indent::IndentRules;
 
 mod snippet;
 pub mod types;
@@ -26,10 +29,17 @@ struct LanguageConfigToml {
     ansi_color: Option<bool>,
     snippets: Option<Vec<String>>,
     indent_width: Option<usize>,
+    expand_tab: Option<bool>,
+    indent_rules: Option<IndentRulesConfig>,
+    text_width: Option<usize>,
     lsp: Option<Vec<String>>,
     formatter: Option<Vec<String>>,
     syntax: Option<String>,
     compiler: Option<CompilerConfig>,
+    comment: Option<CommentConfig>,
+    auto_pairs: Option<Vec<(char, char)>>,
+    time_limit: Option<u64>,
+    memory_limit: Option<u64>,
 }
 
 pub struct LanguageConfig(typemap::TypeMap);
@@ -78,6 +88,12 @@ impl Into<LanguageConfig> for LanguageConfigToml {
         language_config.insert_option::<keys::ANSIColor>(self.ansi_color);
         language_config.0.insert::<keys::Snippets>(snippets);
         language_config.insert_option::<keys::IndentWidth>(self.indent_width);
+        language_config.insert_option::<keys::ExpandTab>(self.expand_tab);
+        language_config.insert_option::<keys::IndentRules>(
+            self.indent_rules
+                .and_then(|r| IndentRules::new(&r.increase, &r.decrease).ok()),
+        );
+        language_config.insert_option::<keys::TextWidth>(self.text_width);
         language_config.insert_option::<keys::LSP>(
             self.lsp.as_ref().map(Vec::as_slice).and_then(Command::new),
         );
@@ -89,6 +105,10 @@ impl Into<LanguageConfig> for LanguageConfigToml {
         );
         language_config.insert_option::<keys::SyntaxExtension>(self.syntax);
         language_config.insert_option::<keys::Compiler>(self.compiler);
+        language_config.insert_option::<keys::Comment>(self.comment);
+        language_config.insert_option::<keys::AutoPairs>(self.auto_pairs);
+        language_config.insert_option::<keys::TimeLimit>(self.time_limit);
+        language_config.insert_option::<keys::MemoryLimit>(self.memory_limit);
 
         language_config
     }
@@ -108,8 +128,32 @@ impl Into<Config> for ConfigToml {
     }
 }
 
+// Errors dropped on the conversion to `Config`
+fn check_config(config: &ConfigToml) -> Result<(), failure::Error> {
+    let files = config
+        .file
+        .iter()
+        .flatten()
+        .map(|(k, v)| (format!("file.{}", k), v))
+        .chain(
+            config
+                .file_default
+                .iter()
+                .map(|v| ("file_default".to_string(), v)),
+        );
+    for (name, language_config) in files {
+        if let Some(r) = language_config.indent_rules.as_ref() {
+            IndentRules::new(&r.increase, &r.decrease).map_err(|e| {
+                failure::err_msg(format!("Invalid indent_rules in [{}]: {}", name, e))
+            })?;
+        }
+    }
+    Ok(())
+}
+
 fn parse_config(s: &str) -> Result<Config, failure::Error> {
     let config_toml: ConfigToml = toml::from_str(&s)?;
+    check_config(&config_toml)?;
     Ok(config_toml.into())
 }
 
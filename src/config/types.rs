@@ -39,6 +39,18 @@ pub struct CompilerConfig {
     pub output_type: Option<CompilerType>,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+pub struct IndentRulesConfig {
+    pub increase: String,
+    pub decrease: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CommentConfig {
+    pub line: Option<String>,
+    pub block: Option<(String, String)>,
+}
+
 //
 
 pub mod keys {
@@ -47,7 +59,9 @@ pub mod keys {
     use typemap::Key;
 
     use crate::config::types::Command;
+    use crate::config::types::CommentConfig;
     use crate::config::types::CompilerConfig;
+    use crate::indent;
 
     pub struct ANSIColor;
 
@@ -67,6 +81,24 @@ pub mod keys {
         type Value = usize;
     }
 
+    pub struct ExpandTab;
+
+    impl Key for ExpandTab {
+        type Value = bool;
+    }
+
+    pub struct IndentRules;
+
+    impl Key for IndentRules {
+        type Value = indent::IndentRules;
+    }
+
+    pub struct TextWidth;
+
+    impl Key for TextWidth {
+        type Value = usize;
+    }
+
     pub struct LSP;
 
     impl Key for LSP {
@@ -85,6 +117,32 @@ pub mod keys {
         type Value = String;
     }
 
+    pub struct AutoPairs;
+
+    impl Key for AutoPairs {
+        type Value = Vec<(char, char)>;
+    }
+
+    // In milliseconds
+    pub struct TimeLimit;
+
+    impl Key for TimeLimit {
+        type Value = u64;
+    }
+
+    // In megabytes
+    pub struct MemoryLimit;
+
+    impl Key for MemoryLimit {
+        type Value = u64;
+    }
+
+    pub struct Comment;
+
+    impl Key for Comment {
+        type Value = CommentConfig;
+    }
+
     pub struct Compiler;
 
     impl Key for Compiler {
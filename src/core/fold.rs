@@ -0,0 +1,136 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use ropey::Rope;
+
+use crate::parenthesis;
+use crate::ropey_util::RopeExt;
+
+// Closed folds by their first and last rows. Nested folds stay closed when the outer one is opened.
+#[derive(Default, Debug)]
+pub struct Folds {
+    folds: BTreeMap<usize, usize>,
+}
+
+impl Folds {
+    // The outermost closed fold containing `row`
+    pub fn get(&self, row: usize) -> Option<(usize, usize)> {
+        self.folds
+            .range(..=row)
+            .find(|(_, &end)| end >= row)
+            .map(|(&start, &end)| (start, end))
+    }
+
+    pub fn close(&mut self, start: usize, end: usize) {
+        self.folds.insert(start, end);
+    }
+
+    pub fn open(&mut self, row: usize) -> bool {
+        if let Some((start, _)) = self.get(row) {
+            self.folds.remove(&start);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.folds.clear();
+    }
+
+    pub fn to_vec(&self) -> Vec<(usize, usize)> {
+        self.folds
+            .iter()
+            .map(|(&start, &end)| (start, end))
+            .collect()
+    }
+
+    // `row` is edited and `diff` lines are inserted (diff > 0) or removed (diff < 0) after it.
+    // Folds including the edited lines are opened.
+    pub fn adjust(&mut self, row: usize, diff: isize) {
+        let removed = if diff < 0 { (-diff) as usize } else { 0 };
+        self.folds = std::mem::take(&mut self.folds)
+            .into_iter()
+            .filter(|&(start, end)| end < row || start > row + removed)
+            .map(|(start, end)| {
+                if start > row {
+                    (
+                        (start as isize + diff) as usize,
+                        (end as isize + diff) as usize,
+                    )
+                } else {
+                    (start, end)
+                }
+            })
+            .collect();
+    }
+}
+
+fn indent_of(buffer: &Rope, row: usize) -> Option<usize> {
+    let line = Cow::from(buffer.l(row));
+    if line.trim().is_empty() {
+        None
+    } else {
+        Some(line.chars().take_while(|&c| c == ' ' || c == '\t').count())
+    }
+}
+
+// The last row of the fold starting at `start`. A line ending with an opening bracket folds
+// to its pair and other lines fold the following lines indented deeper.
+fn fold_end(buffer: &Rope, start: usize) -> Option<usize> {
+    let line = Cow::from(buffer.l(start));
+    let line = line.trim_end();
+    if let Some(&(left, right)) = parenthesis::PARENTHESIS_PAIRS
+        .iter()
+        .find(|&&(l, _)| line.ends_with(l))
+    {
+        let mut depth = 0;
+        for row in start + 1..buffer.len_lines() {
+            for c in buffer.l(row).chars() {
+                if c == left {
+                    depth += 1;
+                } else if c == right {
+                    if depth == 0 {
+                        return Some(row);
+                    }
+                    depth -= 1;
+                }
+            }
+        }
+        return None;
+    }
+
+    let base = indent_of(buffer, start)?;
+    let mut end = None;
+    for row in start + 1..buffer.len_lines() {
+        match indent_of(buffer, row) {
+            Some(indent) if indent > base => end = Some(row),
+            Some(_) => break,
+            None => {}
+        }
+    }
+    end
+}
+
+// The innermost fold containing `row`
+pub fn fold_range(buffer: &Rope, row: usize) -> Option<(usize, usize)> {
+    (0..=row)
+        .rev()
+        .filter_map(|start| fold_end(buffer, start).map(|end| (start, end)))
+        .find(|&(_, end)| end >= row)
+}
+
+// Top level folds of the buffer
+pub fn all_folds(buffer: &Rope) -> Vec<(usize, usize)> {
+    let mut res = Vec::new();
+    let mut row = 0;
+    while row < buffer.len_lines() {
+        if let Some(end) = fold_end(buffer, row) {
+            res.push((row, end));
+            row = end + 1;
+        } else {
+            row += 1;
+        }
+    }
+    res
+}
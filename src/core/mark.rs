@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+
+// Marks set by `m` and the jump list for Ctrl-o / Ctrl-i.
+// Positions are char indices to follow insertions and deletions before them.
+#[derive(Default, Debug)]
+pub struct Marks {
+    marks: BTreeMap<char, usize>,
+    jumps: Vec<usize>,
+    // jumps.len() unless going back by Ctrl-o
+    jump_index: usize,
+}
+
+impl Marks {
+    const JUMPS_LEN: usize = 100;
+
+    pub fn get(&self, name: char) -> Option<usize> {
+        self.marks.get(&name).cloned()
+    }
+
+    pub fn set(&mut self, name: char, index: usize) {
+        self.marks.insert(name, index);
+    }
+
+    pub fn remove(&mut self, name: char) {
+        self.marks.remove(&name);
+    }
+
+    pub fn clear(&mut self) {
+        self.marks.clear();
+        self.jumps.clear();
+        self.jump_index = 0;
+    }
+
+    // Remember the position before a jump
+    pub fn push_jump(&mut self, index: usize) {
+        self.jumps.retain(|&j| j != index);
+        self.jumps.push(index);
+        if self.jumps.len() > Self::JUMPS_LEN {
+            self.jumps.remove(0);
+        }
+        self.jump_index = self.jumps.len();
+    }
+
+    // Ctrl-o from `index`
+    pub fn older(&mut self, index: usize) -> Option<usize> {
+        if self.jump_index == self.jumps.len() {
+            // Come back here by Ctrl-i
+            self.push_jump(index);
+            self.jump_index -= 1;
+        }
+        if self.jump_index == 0 {
+            return None;
+        }
+        self.jump_index -= 1;
+        Some(self.jumps[self.jump_index])
+    }
+
+    // Ctrl-i
+    pub fn newer(&mut self) -> Option<usize> {
+        if self.jump_index + 1 >= self.jumps.len() {
+            return None;
+        }
+        self.jump_index += 1;
+        Some(self.jumps[self.jump_index])
+    }
+
+    // `diff` chars are inserted (diff > 0) or removed (diff < 0) at `start`.
+    // Positions in removed text move to `start`.
+    pub fn adjust(&mut self, start: usize, diff: isize) {
+        let f = |i: &mut usize| {
+            if diff >= 0 {
+                if *i >= start {
+                    *i += diff as usize;
+                }
+            } else {
+                let len = (-diff) as usize;
+                if *i >= start + len {
+                    *i -= len;
+                } else if *i > start {
+                    *i = start;
+                }
+            }
+        };
+        self.marks.values_mut().for_each(f);
+        self.jumps.iter_mut().for_each(f);
+    }
+}
@@ -5,15 +5,21 @@ use std::cmp::{max, min};
 use std::io;
 use std::io::Read;
 use std::num::Wrapping;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use ropey::{self, Rope, RopeSlice};
+use serde_derive::{Deserialize, Serialize};
 
-use crate::indent;
+use crate::indent::{self, IndentRules};
 use crate::parenthesis;
 use crate::ropey_util::{is_line_end, RopeExt};
 
-use self::operation::{Operation, OperationArg};
+use self::fold::Folds;
+use self::mark::Marks;
+use self::operation::{Operation, OperationArg, OperationData};
 
+mod fold;
+mod mark;
 pub mod operation;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
@@ -25,7 +31,7 @@ impl Id {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Cursor {
     pub row: usize,
     pub col: usize,
@@ -49,7 +55,7 @@ impl Ord for Cursor {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct CursorRange(pub Cursor, pub Cursor);
 
 impl CursorRange {
@@ -82,15 +88,64 @@ impl CursorRange {
     }
 }
 
+// A state in the undo tree. `ops` change the parent state to this state.
+#[derive(Debug)]
+struct UndoNode {
+    parent: usize,
+    ops: Vec<Box<dyn Operation>>,
+    // Child to redo. The last visited one.
+    redo: Option<usize>,
+    // Seconds since UNIX_EPOCH at commit
+    time: u64,
+}
+
+impl UndoNode {
+    fn root() -> Self {
+        Self {
+            parent: 0,
+            ops: Vec::new(),
+            redo: None,
+            time: now(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct HistoryNode {
+    parent: usize,
+    ops: Vec<OperationData>,
+    redo: Option<usize>,
+    time: u64,
+}
+
+// Undo tree in the serializable form
+#[derive(Serialize, Deserialize)]
+pub struct History {
+    nodes: Vec<HistoryNode>,
+    state: usize,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
 #[derive(Debug)]
 pub struct Core {
     buffer: Rope,
     cursor: Cursor,
-    history: Vec<Vec<Box<Operation>>>,
-    history_tmp: Vec<Box<Operation>>,
-    redo: Vec<Vec<Box<Operation>>>,
+    // Cursors other than the main cursor. Edits apply at all of them.
+    cursors: Vec<Cursor>,
+    // Nodes are in chronological order and the first is the root
+    undo_tree: Vec<UndoNode>,
+    undo_state: usize,
+    history_tmp: Vec<Box<dyn Operation>>,
     buffer_changed: Id,
     pub dirty_from: usize,
+    marks: Marks,
+    folds: Folds,
 }
 
 impl Default for Core {
@@ -98,11 +153,14 @@ impl Default for Core {
         Self {
             buffer: Rope::default(),
             cursor: Cursor { row: 0, col: 0 },
-            history: Vec::new(),
+            cursors: Vec::new(),
+            undo_tree: vec![UndoNode::root()],
+            undo_state: 0,
             history_tmp: Vec::new(),
-            redo: Vec::new(),
             buffer_changed: Id(Wrapping(1)),
             dirty_from: 0,
+            marks: Marks::default(),
+            folds: Folds::default(),
         }
     }
 }
@@ -112,11 +170,14 @@ impl Core {
         Ok(Self {
             buffer: Rope::from_reader(reader)?,
             cursor: Cursor { row: 0, col: 0 },
-            history: Vec::new(),
+            cursors: Vec::new(),
+            undo_tree: vec![UndoNode::root()],
+            undo_state: 0,
             history_tmp: Vec::new(),
-            redo: Vec::new(),
             buffer_changed: Id(Wrapping(1)),
             dirty_from: 0,
+            marks: Marks::default(),
+            folds: Folds::default(),
         })
     }
 
@@ -149,6 +210,16 @@ impl Core {
         self.current_line().slice(self.cursor.col..)
     }
 
+    // Like `^`
+    pub fn first_non_blank(&mut self) {
+        let line = self.current_line();
+        let col = line
+            .chars()
+            .position(|c| c != ' ' && c != '\t')
+            .unwrap_or_else(|| line.len_chars());
+        self.cursor.col = min(col, line.len_chars());
+    }
+
     pub fn cursor_left(&mut self) {
         if self.cursor.col != 0 {
             self.cursor.col -= 1;
@@ -162,16 +233,21 @@ impl Core {
         );
     }
 
+    // Closed folds are passed as one line
     pub fn cursor_up(&mut self) {
-        if self.cursor.row != 0 {
-            self.cursor.row -= 1;
+        let row = self.fold(self.cursor.row).map_or(self.cursor.row, |f| f.0);
+        if row != 0 {
+            self.cursor.row = self.fold(row - 1).map_or(row - 1, |f| f.0);
             self.cursor.col = min(self.buffer.l(self.cursor.row).len_chars(), self.cursor.col);
         }
     }
 
     pub fn cursor_down(&mut self) {
-        self.cursor.row = min(self.buffer.len_lines() - 1, self.cursor.row + 1);
-        self.cursor.col = min(self.buffer.l(self.cursor.row).len_chars(), self.cursor.col);
+        let row = self.fold(self.cursor.row).map_or(self.cursor.row, |f| f.1);
+        if row + 1 < self.buffer.len_lines() {
+            self.cursor.row = row + 1;
+            self.cursor.col = min(self.buffer.l(self.cursor.row).len_chars(), self.cursor.col);
+        }
     }
 
     pub fn cursor_inc(&mut self) -> bool {
@@ -240,19 +316,35 @@ impl Core {
         })
     }
 
-    pub fn indent(&mut self, indent_width: usize) {
+    pub fn indent(&mut self, rules: &IndentRules, indent_width: usize, expand_tab: bool) {
         self.cursor.col = 0;
         if self.cursor.row > 0 {
-            let indent = indent::next_indent_level(
+            let indent = rules.indent(
                 &Cow::from(self.buffer.l(self.cursor.row - 1)),
+                &Cow::from(self.current_line()),
                 indent_width,
             );
-            for _ in 0..indent_width * indent {
-                self.insert(' ');
+            for c in indent::indent_string(indent, indent_width, expand_tab).chars() {
+                self.insert(c);
             }
         }
     }
 
+    // Replace the indent of the cursor line. The cursor stays on the same char.
+    pub fn set_indent(&mut self, indent: &str) {
+        let len = indent::indent_len(&Cow::from(self.current_line()));
+        let width = indent.chars().count();
+        let col = self.cursor.col;
+        self.cursor.col = 0;
+        for _ in 0..len {
+            self.delete();
+        }
+        for c in indent.chars() {
+            self.insert(c);
+        }
+        self.cursor.col = if col >= len { col - len + width } else { width };
+    }
+
     pub fn w(&mut self) {
         if self
             .char_at_cursor()
@@ -276,6 +368,7 @@ impl Core {
                 .unwrap_or(true)
                 && self.cursor_inc()
         } {}
+        self.open_fold_at_cursor();
     }
 
     pub fn b(&mut self) {
@@ -289,9 +382,15 @@ impl Core {
         if self.char_at_cursor().map(char::is_alphanumeric) != Some(true) {
             self.cursor_inc();
         }
+        self.open_fold_at_cursor();
     }
 
     pub fn e(&mut self) {
+        self.word_end();
+        self.open_fold_at_cursor();
+    }
+
+    fn word_end(&mut self) {
         self.cursor_inc();
         if self
             .char_at_cursor()
@@ -370,6 +469,72 @@ impl Core {
         self.perform(op);
     }
 
+    pub fn cursors(&self) -> &[Cursor] {
+        &self.cursors
+    }
+
+    // The new cursor becomes the main cursor
+    pub fn add_cursor(&mut self, cursor: Cursor) {
+        if cursor != self.cursor && !self.cursors.contains(&cursor) {
+            self.cursors.push(self.cursor);
+            self.set_cursor(cursor);
+        }
+    }
+
+    pub fn clear_cursors(&mut self) {
+        self.cursors.clear();
+    }
+
+    fn cursor_to_index(&self, cursor: Cursor) -> usize {
+        let row = min(cursor.row, self.buffer.len_lines() - 1);
+        let col = min(cursor.col, self.buffer.l(row).len_chars());
+        self.buffer.line_to_char(row) + col
+    }
+
+    fn index_to_cursor(&self, index: usize) -> Cursor {
+        let index = min(index, self.buffer.len_chars());
+        let row = self.buffer.char_to_line(index);
+        let col = min(
+            index - self.buffer.line_to_char(row),
+            self.buffer.l(row).len_chars(),
+        );
+        Cursor { row, col }
+    }
+
+    // Run `f` at each cursor from the last one so that edits don't move cursors yet to run.
+    // Cursors after the edit are shifted by the change of length.
+    pub fn for_each_cursor<F: FnMut(&mut Self)>(&mut self, mut f: F) {
+        if self.cursors.is_empty() {
+            f(self);
+            return;
+        }
+
+        let mut cursors = std::mem::take(&mut self.cursors);
+        cursors.insert(0, self.cursor);
+        let mut indices: Vec<usize> = cursors.iter().map(|&c| self.cursor_to_index(c)).collect();
+        let mut order: Vec<usize> = (0..indices.len()).collect();
+        order.sort_by_key(|&k| std::cmp::Reverse(indices[k]));
+
+        for (i, &k) in order.iter().enumerate() {
+            self.cursor = self.index_to_cursor(indices[k]);
+            let len = self.buffer.len_chars();
+            f(self);
+            let new_len = self.buffer.len_chars();
+            for &done in &order[..i] {
+                indices[done] = (indices[done] + new_len).saturating_sub(len);
+            }
+            indices[k] = self.cursor_to_index(self.cursor);
+        }
+
+        self.cursor = self.index_to_cursor(indices[0]);
+        for &index in &indices[1..] {
+            let cursor = self.index_to_cursor(index);
+            if cursor != self.cursor && !self.cursors.contains(&cursor) {
+                self.cursors.push(cursor);
+            }
+        }
+    }
+
     pub fn delete_range(&mut self, range: CursorRange) {
         let op = operation::DeleteRange::new(range);
         self.perform(op);
@@ -405,9 +570,12 @@ impl Core {
             self.buffer = Rope::from(s);
             self.buffer_changed.inc();
             self.dirty_from = 0;
-            self.redo.clear();
-            self.history.clear();
+            self.undo_tree = vec![UndoNode::root()];
+            self.undo_state = 0;
             self.history_tmp.clear();
+            self.cursors.clear();
+            self.marks.clear();
+            self.folds.clear();
         } else {
             let op = operation::Set::new(s);
             self.perform(op);
@@ -426,6 +594,108 @@ impl Core {
         assert!(cursor.row < self.buffer.len_lines());
         assert!(cursor.col <= self.buffer.l(cursor.row).len_chars());
         self.cursor = cursor;
+        self.open_fold_at_cursor();
+    }
+
+    pub fn mark(&self, name: char) -> Option<Cursor> {
+        self.marks.get(name).map(|i| self.index_to_cursor(i))
+    }
+
+    pub fn set_mark(&mut self, name: char) {
+        let i = self.cursor_to_index(self.cursor);
+        self.marks.set(name, i);
+    }
+
+    pub fn remove_mark(&mut self, name: char) {
+        self.marks.remove(name);
+    }
+
+    // Call before a jump to come back by Ctrl-o
+    pub fn push_jump(&mut self) {
+        let i = self.cursor_to_index(self.cursor);
+        self.marks.push_jump(i);
+    }
+
+    // Ctrl-o
+    pub fn jump_older(&mut self) -> bool {
+        let i = self.cursor_to_index(self.cursor);
+        if let Some(i) = self.marks.older(i) {
+            self.cursor = self.index_to_cursor(i);
+            true
+        } else {
+            false
+        }
+    }
+
+    // Ctrl-i
+    pub fn jump_newer(&mut self) -> bool {
+        if let Some(i) = self.marks.newer() {
+            self.cursor = self.index_to_cursor(i);
+            true
+        } else {
+            false
+        }
+    }
+
+    // The closed fold containing `row`
+    pub fn fold(&self, row: usize) -> Option<(usize, usize)> {
+        self.folds.get(row)
+    }
+
+    pub fn folds(&self) -> Vec<(usize, usize)> {
+        self.folds.to_vec()
+    }
+
+    pub fn set_folds(&mut self, folds: &[(usize, usize)]) {
+        self.folds.clear();
+        for &(start, end) in folds {
+            if start <= end && end < self.buffer.len_lines() {
+                self.folds.close(start, end);
+            }
+        }
+    }
+
+    // zc
+    pub fn close_fold(&mut self) -> bool {
+        if let Some((start, end)) = fold::fold_range(&self.buffer, self.cursor.row) {
+            self.folds.close(start, end);
+            self.cursor = Cursor { row: start, col: 0 };
+            self.first_non_blank();
+            true
+        } else {
+            false
+        }
+    }
+
+    // zo
+    pub fn open_fold(&mut self) -> bool {
+        self.folds.open(self.cursor.row)
+    }
+
+    // zM
+    pub fn close_all_folds(&mut self) {
+        for (start, end) in fold::all_folds(&self.buffer) {
+            self.folds.close(start, end);
+        }
+        if let Some((start, _)) = self.fold(self.cursor.row) {
+            self.cursor = Cursor { row: start, col: 0 };
+            self.first_non_blank();
+        }
+    }
+
+    // zR
+    pub fn open_all_folds(&mut self) {
+        self.folds.clear();
+    }
+
+    // Open closed folds hiding the cursor like Vim's `foldopen`. The first row of a fold is shown.
+    fn open_fold_at_cursor(&mut self) {
+        while let Some((start, _)) = self.fold(self.cursor.row) {
+            if start == self.cursor.row {
+                break;
+            }
+            self.folds.open(self.cursor.row);
+        }
     }
 
     fn arg(&mut self) -> OperationArg {
@@ -435,45 +705,211 @@ impl Core {
         }
     }
 
-    fn perform<T: Operation + 'static>(&mut self, mut op: T) {
-        if let Some(l) = op.perform(self.arg()) {
-            self.dirty_from = min(self.dirty_from, l);
+    // After `op` changed the buffer of `len_chars` chars and `len_lines` lines
+    fn changed(
+        &mut self,
+        op: &dyn Operation,
+        row: Option<usize>,
+        len_chars: usize,
+        len_lines: usize,
+    ) {
+        if let Some(row) = row {
+            self.dirty_from = min(self.dirty_from, row);
+            let diff = self.buffer.len_chars() as isize - len_chars as isize;
+            if diff != 0 {
+                let start = self.cursor_to_index(op.start());
+                self.marks.adjust(start, diff);
+            }
+            let diff_lines = self.buffer.len_lines() as isize - len_lines as isize;
+            self.folds.adjust(op.start().row, diff_lines);
         }
+    }
+
+    fn perform<T: Operation + 'static>(&mut self, mut op: T) {
+        let (len_chars, len_lines) = (self.buffer.len_chars(), self.buffer.len_lines());
+        let row = op.perform(self.arg());
+        self.changed(&op, row, len_chars, len_lines);
         self.history_tmp.push(Box::new(op));
-        self.redo.clear();
         self.buffer_changed.inc();
     }
 
     pub fn commit(&mut self) {
         if !self.history_tmp.is_empty() {
-            let mut h = Vec::new();
-            std::mem::swap(&mut self.history_tmp, &mut h);
-            self.history.push(h);
+            let ops = std::mem::take(&mut self.history_tmp);
+            let node = self.undo_tree.len();
+            self.undo_tree.push(UndoNode {
+                parent: self.undo_state,
+                ops,
+                redo: None,
+                time: now(),
+            });
+            self.undo_tree[self.undo_state].redo = Some(node);
+            self.undo_state = node;
+        }
+    }
+
+    // Move to the parent state
+    fn undo_node(&mut self) {
+        let node = self.undo_state;
+        let mut ops = std::mem::take(&mut self.undo_tree[node].ops);
+        for op in ops.iter_mut().rev() {
+            let (len_chars, len_lines) = (self.buffer.len_chars(), self.buffer.len_lines());
+            let row = op.undo(self.arg());
+            self.changed(op.as_ref(), row, len_chars, len_lines);
         }
+        self.undo_tree[node].ops = ops;
+        let parent = self.undo_tree[node].parent;
+        self.undo_tree[parent].redo = Some(node);
+        self.undo_state = parent;
+        self.buffer_changed.inc();
+    }
+
+    // Move to a child state
+    fn redo_node(&mut self, node: usize) {
+        let mut ops = std::mem::take(&mut self.undo_tree[node].ops);
+        for op in &mut ops {
+            let (len_chars, len_lines) = (self.buffer.len_chars(), self.buffer.len_lines());
+            let row = op.perform(self.arg());
+            self.changed(op.as_ref(), row, len_chars, len_lines);
+        }
+        self.undo_tree[node].ops = ops;
+        let parent = self.undo_tree[node].parent;
+        self.undo_tree[parent].redo = Some(node);
+        self.undo_state = node;
+        self.buffer_changed.inc();
     }
 
     pub fn undo(&mut self) {
         self.commit();
-        if let Some(mut ops) = self.history.pop() {
-            for op in ops.iter_mut().rev() {
-                if let Some(l) = op.undo(self.arg()) {
-                    self.dirty_from = min(self.dirty_from, l);
-                }
-            }
-            self.redo.push(ops);
-            self.buffer_changed.inc();
+        self.cursors.clear();
+        if self.undo_state != 0 {
+            self.undo_node();
         }
     }
 
     pub fn redo(&mut self) {
-        if let Some(mut ops) = self.redo.pop() {
-            for op in &mut ops {
-                if let Some(l) = op.perform(self.arg()) {
-                    self.dirty_from = min(self.dirty_from, l);
-                }
-            }
-            self.history.push(ops);
-            self.buffer_changed.inc();
+        self.commit();
+        self.cursors.clear();
+        if let Some(node) = self.undo_tree[self.undo_state].redo {
+            self.redo_node(node);
+        }
+    }
+
+    // Go to any state through the common ancestor
+    fn goto_state(&mut self, target: usize) {
+        self.commit();
+        self.cursors.clear();
+        let mut is_ancestor = vec![false; self.undo_tree.len()];
+        let mut node = self.undo_state;
+        is_ancestor[node] = true;
+        while node != 0 {
+            node = self.undo_tree[node].parent;
+            is_ancestor[node] = true;
+        }
+
+        let mut path = Vec::new();
+        let mut node = target;
+        while !is_ancestor[node] {
+            path.push(node);
+            node = self.undo_tree[node].parent;
+        }
+
+        while self.undo_state != node {
+            self.undo_node();
+        }
+        for &node in path.iter().rev() {
+            self.redo_node(node);
+        }
+    }
+
+    // g-. Unlike undo, this visits states on other branches.
+    pub fn earlier(&mut self, count: usize) {
+        self.commit();
+        self.goto_state(self.undo_state.saturating_sub(count));
+    }
+
+    // g+
+    pub fn later(&mut self, count: usize) {
+        self.commit();
+        self.goto_state(min(self.undo_state + count, self.undo_tree.len() - 1));
+    }
+
+    // :earlier 10m. The last state at least `secs` before the current one.
+    pub fn earlier_by_time(&mut self, secs: u64) {
+        self.commit();
+        let time = self.undo_tree[self.undo_state].time.saturating_sub(secs);
+        let target = (0..=self.undo_state)
+            .rev()
+            .find(|&i| self.undo_tree[i].time <= time)
+            .unwrap_or(0);
+        self.goto_state(target);
+    }
+
+    // :later 10m. The last state within `secs` after the current one.
+    pub fn later_by_time(&mut self, secs: u64) {
+        self.commit();
+        let time = self.undo_tree[self.undo_state].time.saturating_add(secs);
+        let target = (self.undo_state..self.undo_tree.len())
+            .rev()
+            .find(|&i| self.undo_tree[i].time <= time)
+            .unwrap_or(self.undo_state);
+        self.goto_state(target);
+    }
+
+    pub fn history(&self) -> History {
+        let mut nodes: Vec<HistoryNode> = self
+            .undo_tree
+            .iter()
+            .map(|node| HistoryNode {
+                parent: node.parent,
+                ops: node.ops.iter().map(|op| op.to_data()).collect(),
+                redo: node.redo,
+                time: node.time,
+            })
+            .collect();
+        let mut state = self.undo_state;
+        // Uncommitted changes
+        if !self.history_tmp.is_empty() {
+            nodes[state].redo = Some(nodes.len());
+            nodes.push(HistoryNode {
+                parent: state,
+                ops: self.history_tmp.iter().map(|op| op.to_data()).collect(),
+                redo: None,
+                time: now(),
+            });
+            state = nodes.len() - 1;
+        }
+        History { nodes, state }
+    }
+
+    // The history must be taken from the same content
+    pub fn set_history(&mut self, history: History) {
+        let len = history.nodes.len();
+        // Parents must be older for traversals to terminate
+        let valid =
+            len > 0
+                && history.state < len
+                && history.nodes.iter().enumerate().all(|(i, node)| {
+                    (i == 0 || node.parent < i) && node.redo.iter().all(|&r| r < len)
+                });
+        if !valid {
+            return;
         }
+        self.undo_tree = history
+            .nodes
+            .into_iter()
+            .map(|node| UndoNode {
+                parent: node.parent,
+                ops: node
+                    .ops
+                    .into_iter()
+                    .map(OperationData::into_operation)
+                    .collect(),
+                redo: node.redo,
+                time: node.time,
+            })
+            .collect();
+        self.undo_state = history.state;
+        self.history_tmp.clear();
     }
 }
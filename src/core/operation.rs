@@ -2,6 +2,7 @@ use std::cmp::min;
 use std::fmt::Debug;
 
 use ropey::Rope;
+use serde_derive::{Deserialize, Serialize};
 
 use crate::core::{Cursor, CursorRange};
 use crate::ropey_util::{is_line_end, RopeExt};
@@ -14,15 +15,40 @@ pub struct OperationArg<'a> {
 pub trait Operation: Debug {
     fn perform(&mut self, arg: OperationArg) -> Option<usize>;
     fn undo(&mut self, arg: OperationArg) -> Option<usize>;
+    // Serializable form to save undo history
+    fn to_data(&self) -> OperationData;
+    // Where the change starts. Text before it is not changed by perform or undo.
+    fn start(&self) -> Cursor;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum OperationData {
+    Insert(Insert),
+    Replace(Replace),
+    Delete(Delete),
+    DeleteRange(DeleteRange),
+    Set(Set),
+}
+
+impl OperationData {
+    pub fn into_operation(self) -> Box<dyn Operation> {
+        match self {
+            OperationData::Insert(op) => Box::new(op),
+            OperationData::Replace(op) => Box::new(op),
+            OperationData::Delete(op) => Box::new(op),
+            OperationData::DeleteRange(op) => Box::new(op),
+            OperationData::Set(op) => Box::new(op),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Insert {
     pub cursor: Cursor,
     pub c: char,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Replace {
     pub cursor: Cursor,
     pub c: char,
@@ -39,7 +65,7 @@ impl Replace {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Delete {
     pub cursor: Cursor,
     pub orig: Option<char>,
@@ -56,7 +82,7 @@ impl Delete {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeleteRange {
     pub range: CursorRange,
     orig: Option<String>,
@@ -68,7 +94,7 @@ impl DeleteRange {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Set {
     to: String,
     from: Option<String>,
@@ -101,6 +127,14 @@ impl Operation for Insert {
         *arg.cursor = self.cursor;
         Some(self.cursor.row)
     }
+
+    fn to_data(&self) -> OperationData {
+        OperationData::Insert(self.clone())
+    }
+
+    fn start(&self) -> Cursor {
+        self.cursor
+    }
 }
 
 impl Operation for Replace {
@@ -124,6 +158,14 @@ impl Operation for Replace {
         *arg.cursor = self.cursor;
         Some(self.cursor.row)
     }
+
+    fn to_data(&self) -> OperationData {
+        OperationData::Replace(self.clone())
+    }
+
+    fn start(&self) -> Cursor {
+        self.cursor
+    }
 }
 
 impl Operation for Delete {
@@ -165,6 +207,14 @@ impl Operation for Delete {
         *arg.cursor = self.cursor;
         Some(self.cursor.row)
     }
+
+    fn to_data(&self) -> OperationData {
+        OperationData::Delete(self.clone())
+    }
+
+    fn start(&self) -> Cursor {
+        self.cursor
+    }
 }
 
 impl Operation for DeleteRange {
@@ -199,6 +249,14 @@ impl Operation for DeleteRange {
         *arg.cursor = self.range.l();
         Some(self.range.l().row)
     }
+
+    fn to_data(&self) -> OperationData {
+        OperationData::DeleteRange(self.clone())
+    }
+
+    fn start(&self) -> Cursor {
+        self.range.l()
+    }
 }
 
 impl Operation for Set {
@@ -219,4 +277,12 @@ impl Operation for Set {
         arg.cursor.col = min(arg.buffer.l(arg.cursor.row).len_chars(), arg.cursor.col);
         Some(0)
     }
+
+    fn to_data(&self) -> OperationData {
+        OperationData::Set(self.clone())
+    }
+
+    fn start(&self) -> Cursor {
+        Cursor { row: 0, col: 0 }
+    }
 }
@@ -113,6 +113,30 @@ pub mod styles {
         bg: Color { r: 0, g: 0, b: 0 },
         modification: CharModification::Default,
     };
+    // AC of test cases
+    pub const ACCEPTED: CharStyle = CharStyle {
+        fg: Color {
+            r: 0x00,
+            g: 0xc8,
+            b: 0x40,
+        },
+        bg: Color { r: 0, g: 0, b: 0 },
+        modification: CharModification::Default,
+    };
+    // Differing chars of outputs
+    pub const DIFF: CharStyle = CharStyle {
+        fg: Color {
+            r: 255,
+            g: 255,
+            b: 255,
+        },
+        bg: Color {
+            r: 0x90,
+            g: 0x20,
+            b: 0x20,
+        },
+        modification: CharModification::Default,
+    };
     pub const UI: CharStyle = CharStyle {
         fg: Color {
             r: 128,
@@ -144,6 +168,19 @@ pub mod styles {
         bg: Color { r: 0, g: 0, b: 0 },
         modification: CharModification::Default,
     };
+    pub const MATCHING: CharStyle = CharStyle {
+        fg: Color {
+            r: 255,
+            g: 255,
+            b: 255,
+        },
+        bg: Color {
+            r: 0x60,
+            g: 0x60,
+            b: 0x60,
+        },
+        modification: CharModification::Default,
+    };
     pub const TAB_BAR: CharStyle = CharStyle {
         fg: Color {
             r: 0xee,
@@ -1,5 +1,6 @@
 use std;
 use std::cell::RefCell;
+use std::cmp::max;
 use std::io::{self, Write};
 
 use termion;
@@ -38,6 +39,9 @@ pub struct TermView<'a> {
     width: usize,
     pub bg: Option<Color>,
     pub cursor: Cursor,
+    pub tab_width: usize,
+    // Column where tab stops start
+    line_start: usize,
 }
 
 pub struct LinenumView<'a> {
@@ -97,6 +101,7 @@ impl<'a> LinenumView<'a> {
                 self.view.put(' ', styles::UI, None);
             }
         }
+        self.view.set_line_start();
     }
 
     pub fn cursor(&self) -> Option<Cursor> {
@@ -115,6 +120,7 @@ impl<'a> LinenumView<'a> {
         for _ in 0..self.width {
             self.view.put(' ', styles::UI, None);
         }
+        self.view.set_line_start();
     }
 
     pub fn put(&mut self, c: char, style: CharStyle, pos: Option<Cursor>) -> Option<Cursor> {
@@ -130,6 +136,12 @@ impl<'a> LinenumView<'a> {
         self.view.newline();
         self.put_linenum();
     }
+
+    // Newline after a closed fold hiding `hidden` lines
+    pub fn newline_after_fold(&mut self, hidden: usize) {
+        self.current_linenum += hidden;
+        self.newline();
+    }
 }
 
 impl Default for Term {
@@ -173,6 +185,8 @@ impl Term {
                 row: orig.0,
                 col: orig.1,
             },
+            tab_width: 8,
+            line_start: orig.1,
         }
     }
 
@@ -218,6 +232,8 @@ impl<'a> TermView<'a> {
                 row: new_orig.0,
                 col: new_orig.1,
             },
+            tab_width: self.tab_width,
+            line_start: new_orig.1,
         }
     }
 
@@ -245,16 +261,31 @@ impl<'a> TermView<'a> {
             }
             self.cursor.row += 1;
             self.cursor.col = self.orig.1;
+            self.line_start = self.orig.1;
             Some(prev)
         }
     }
 
+    // Tab stops are counted from here
+    pub fn set_line_start(&mut self) {
+        self.line_start = self.cursor.col;
+    }
+
+    fn char_width(&self, c: char) -> usize {
+        if c == '\t' {
+            let tab_width = max(self.tab_width, 1);
+            tab_width - self.cursor.col.saturating_sub(self.line_start) % tab_width
+        } else {
+            c.width().unwrap_or(0)
+        }
+    }
+
     pub fn cause_newline(&self, c: char) -> bool {
         if self.is_out() {
             return true;
         }
 
-        let w = c.width().unwrap_or(0);
+        let w = self.char_width(c);
         self.cursor.col + w >= self.orig.1 + self.width
     }
 
@@ -264,19 +295,26 @@ impl<'a> TermView<'a> {
         }
 
         let prev = self.cursor;
-        let w = c.width().unwrap_or(0);
+        let mut w = self.char_width(c);
         if w > 0 {
             if self.cursor.col + w >= self.orig.1 + self.width {
                 self.newline();
                 if self.is_out() {
                     return None;
                 }
+                w = self.char_width(c);
             }
+            // A tab is spaces to the next tab stop
+            let (c, rest) = if c == '\t' {
+                (' ', Tile::Char(' ', style, pos))
+            } else {
+                (c, Tile::Empty)
+            };
             self.parent.buf.borrow_mut()[self.cursor.row][self.cursor.col] =
                 Tile::Char(c, style, pos);
             self.cursor.col += 1;
             for _ in 1..w {
-                self.parent.buf.borrow_mut()[self.cursor.row][self.cursor.col] = Tile::Empty;
+                self.parent.buf.borrow_mut()[self.cursor.row][self.cursor.col] = rest.clone();
                 self.cursor.col += 1;
             }
             Some(prev)
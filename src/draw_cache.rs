@@ -6,7 +6,7 @@ use std::time::{Duration, Instant};
 use ropey::Rope;
 use syntect::highlighting::{HighlightIterator, HighlightState, Highlighter};
 use syntect::parsing::SyntaxSet;
-use syntect::parsing::{ParseState, ScopeStack, ScopeStackOp};
+use syntect::parsing::{ParseState, Scope, ScopeStack, ScopeStackOp};
 
 use crate::draw::CharStyle;
 use crate::draw::Color;
@@ -21,6 +21,19 @@ struct DrawState {
     ops: Vec<(usize, ScopeStackOp)>,
     // 3 of { ( [
     parens_level: [usize; 3],
+    scope_stack: ScopeStack,
+    // Brackets in these scopes are not code
+    string_scope: Scope,
+    comment_scope: Scope,
+    line_comment_scope: Scope,
+}
+
+struct CachedLine {
+    draw: Vec<(char, CharStyle)>,
+    // Columns of brackets outside strings and comments
+    brackets: Vec<usize>,
+    // Whether each column to insert at is in a string or a comment
+    literal: Vec<bool>,
 }
 
 impl DrawState {
@@ -85,17 +98,85 @@ impl DrawState {
             highlight_state: HighlightState::new(highlighter, ScopeStack::new()),
             ops: Vec::new(),
             parens_level: [0, 0, 0],
+            scope_stack: ScopeStack::new(),
+            string_scope: Scope::new("string").unwrap(),
+            comment_scope: Scope::new("comment").unwrap(),
+            line_comment_scope: Scope::new("comment.line").unwrap(),
         }
     }
 
+    fn in_scope(&self, scope: Scope) -> bool {
+        self.scope_stack
+            .as_slice()
+            .iter()
+            .any(|&s| scope.is_prefix_of(s))
+    }
+
+    fn in_literal(&self) -> bool {
+        self.in_scope(self.string_scope) || self.in_scope(self.comment_scope)
+    }
+
+    // Brackets outside strings and comments and whether each column is in them.
+    // Call after parsing the line.
+    fn scan_scopes(&mut self, line: &str) -> (Vec<usize>, Vec<bool>) {
+        let mut brackets = Vec::new();
+        let mut literal = Vec::new();
+        let mut ops = self.ops.iter().peekable();
+        let ends = line
+            .char_indices()
+            .map(|(i, c)| (i, Some(c)))
+            .chain(std::iter::once((line.len(), None)));
+        for (col, (i, c)) in ends.enumerate() {
+            // A column between two chars is in a literal only if both sides are
+            let before = self.in_literal();
+            let in_line_comment = self.in_scope(self.line_comment_scope);
+            while let Some((_, op)) = ops.peek().filter(|(pos, _)| *pos <= i) {
+                self.scope_stack.apply(op);
+                ops.next();
+            }
+            let after = self.in_literal();
+            if let Some(c) = c {
+                literal.push(before && after);
+                if parenthesis::PARENTHESIS_PAIRS
+                    .iter()
+                    .any(|&(l, r)| c == l || c == r)
+                    && !after
+                {
+                    brackets.push(col);
+                }
+            } else {
+                // A line comment ends with the line but its end is still in the comment
+                literal.push(before && after || in_line_comment);
+            }
+        }
+        (brackets, literal)
+    }
+
+    // Rainbow color of a bracket
+    fn paren_color(parens_level: &mut [usize; 3], c: char) -> Option<Color> {
+        for (k, (l, r)) in parenthesis::PARENTHESIS_PAIRS.iter().enumerate() {
+            if c == *l {
+                let fg = Self::RAINBOW[parens_level[k] % Self::RAINBOW.len()];
+                parens_level[k] += 1;
+                return Some(fg);
+            }
+            if c == *r && parens_level[k] > 0 {
+                parens_level[k] -= 1;
+                return Some(Self::RAINBOW[parens_level[k] % Self::RAINBOW.len()]);
+            }
+        }
+        None
+    }
+
     fn highlight(
         &mut self,
         line: &str,
         syntax_set: &SyntaxSet,
         highlighter: &Highlighter,
         bg: Color,
-    ) -> Vec<(char, CharStyle)> {
+    ) -> CachedLine {
         self.ops = self.parse_state.parse_line(line, syntax_set);
+        let (brackets, literal) = self.scan_scopes(line);
 
         let highlight_state = &mut self.highlight_state;
         let parens_level = &mut self.parens_level;
@@ -104,51 +185,41 @@ impl DrawState {
         let iter: HighlightIterator =
             HighlightIterator::new(highlight_state, ops, line, highlighter);
 
-        iter.flat_map(move |(style, s)| {
-            s.chars()
-                .map(|c| {
-                    for (k, (l, r)) in parenthesis::PARENTHESIS_PAIRS.iter().enumerate() {
-                        if c == *l {
-                            let fg = Self::RAINBOW[parens_level[k] % Self::RAINBOW.len()];
-                            parens_level[k] += 1;
-                            let style = CharStyle::fg_bg(fg, bg);
-                            return (c, style);
-                        }
-                        if c == *r && parens_level[k] > 0 {
-                            parens_level[k] -= 1;
-                            let fg = Self::RAINBOW[parens_level[k] % Self::RAINBOW.len()];
-                            let style = CharStyle::fg_bg(fg, bg);
-                            return (c, style);
-                        }
-                    }
-                    (c, style.into())
-                })
-                .collect::<Vec<_>>()
-                .into_iter()
-        })
-        .collect()
+        let mut draw = Vec::new();
+        for (style, s) in iter {
+            for c in s.chars() {
+                let color = if brackets.binary_search(&draw.len()).is_ok() {
+                    Self::paren_color(parens_level, c)
+                } else {
+                    None
+                };
+                let style = color
+                    .map(|fg| CharStyle::fg_bg(fg, bg))
+                    .unwrap_or_else(|| style.into());
+                draw.push((c, style));
+            }
+        }
+        CachedLine {
+            draw,
+            brackets,
+            literal,
+        }
     }
 
     fn next(&mut self, line: &str, syntax_set: &SyntaxSet, highlighter: &Highlighter) {
         self.ops = self.parse_state.parse_line(line, syntax_set);
+        let (brackets, _) = self.scan_scopes(line);
 
         let highlight_state = &mut self.highlight_state;
-        let parens_level = &mut self.parens_level;
         let ops = &self.ops[..];
 
         let iter: HighlightIterator =
             HighlightIterator::new(highlight_state, ops, line, highlighter);
+        iter.for_each(drop);
 
-        for (_style, s) in iter {
-            for c in s.chars() {
-                for (k, (l, r)) in parenthesis::PARENTHESIS_PAIRS.iter().enumerate() {
-                    if c == *l {
-                        parens_level[k] += 1;
-                    }
-                    if c == *r && parens_level[k] > 0 {
-                        parens_level[k] -= 1;
-                    }
-                }
+        for (col, c) in line.chars().enumerate() {
+            if brackets.binary_search(&col).is_ok() {
+                Self::paren_color(&mut self.parens_level, c);
             }
         }
     }
@@ -160,8 +231,8 @@ pub struct DrawCache<'a> {
     highlighter: Highlighter<'a>,
     bg: Color,
     state_cache: Vec<DrawState>,
-    draw_cache: HashMap<usize, Vec<(char, CharStyle)>>,
-    draw_cache_pseudo: HashMap<usize, Vec<(char, CharStyle)>>,
+    draw_cache: HashMap<usize, CachedLine>,
+    draw_cache_pseudo: HashMap<usize, CachedLine>,
 }
 
 impl<'a> DrawCache<'a> {
@@ -261,11 +332,24 @@ impl<'a> DrawCache<'a> {
         }
     }
 
-    pub fn get_line(&self, i: usize) -> Option<&[(char, CharStyle)]> {
+    fn cached_line(&self, i: usize) -> Option<&CachedLine> {
         self.draw_cache
             .get(&i)
-            .map(Vec::as_slice)
-            .or_else(|| self.draw_cache_pseudo.get(&i).map(Vec::as_slice))
+            .or_else(|| self.draw_cache_pseudo.get(&i))
+    }
+
+    pub fn get_line(&self, i: usize) -> Option<&[(char, CharStyle)]> {
+        self.cached_line(i).map(|line| line.draw.as_slice())
+    }
+
+    pub fn get_brackets(&self, i: usize) -> Option<&[usize]> {
+        self.cached_line(i).map(|line| line.brackets.as_slice())
+    }
+
+    pub fn in_literal(&self, i: usize, col: usize) -> bool {
+        self.cached_line(i)
+            .and_then(|line| line.literal.get(col).cloned())
+            .unwrap_or(false)
     }
 
     pub fn dirty_from(&mut self, dirty_from: usize) {
@@ -0,0 +1,537 @@
+use std::borrow::Cow;
+use std::cmp::min;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+use failure::err_msg;
+use regex::{Regex, RegexBuilder};
+
+use crate::buffer::Buffer;
+use crate::core::{Cursor, CursorRange};
+use crate::register::Yank;
+use crate::ropey_util::RopeExt;
+use crate::test_case::{self, Limits};
+
+// What to do after a command
+pub enum ExResult {
+    Message(Option<String>),
+    Quit,
+}
+
+// Run a command line like `%s/foo/bar/g`.
+// All edits by the command are undone at once.
+pub fn execute(buf: &mut Buffer, line: &str) -> Result<ExResult, failure::Error> {
+    // Other cursors would be left at stale positions by the edits
+    buf.core.clear_cursors();
+    buf.core.commit();
+    let res = run(buf, line, false);
+    buf.core.commit();
+    res
+}
+
+fn run(buf: &mut Buffer, line: &str, nested: bool) -> Result<ExResult, failure::Error> {
+    let mut s = line.trim_start_matches(|c: char| c == ':' || c.is_whitespace());
+    let range = parse_range(buf, &mut s)?;
+    let s = s.trim();
+    let name_len = s
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(s.len());
+    let (name, args) = s.split_at(name_len);
+    let row = buf.core.cursor().row;
+
+    match name {
+        "" if args.starts_with('!') => {
+            let (l, r) = range.ok_or_else(|| err_msg("Range required for a filter"))?;
+            let lines = filter(buf, l, r, &args[1..])?;
+            Ok(ExResult::Message(Some(format!("{} lines filtered", lines))))
+        }
+        // :<line>
+        "" => {
+            if !args.is_empty() {
+                return Err(err_msg(format!("Not an editor command: {}", s)));
+            }
+            if let Some((_, r)) = range {
+                buf.core.push_jump();
+                buf.core.set_cursor(Cursor { row: r, col: 0 });
+            }
+            Ok(ExResult::Message(None))
+        }
+        "s" | "substitute" => {
+            let (count, lines) = substitute(buf, range.unwrap_or((row, row)), args)?;
+            if count == 0 {
+                if nested {
+                    Ok(ExResult::Message(None))
+                } else {
+                    Err(err_msg("Pattern not found"))
+                }
+            } else {
+                Ok(ExResult::Message(Some(format!(
+                    "{} substitutions on {} lines",
+                    count, lines
+                ))))
+            }
+        }
+        "g" | "global" | "v" | "vglobal" if !nested => {
+            let invert = name.starts_with('v');
+            global(buf, range, args, invert)
+        }
+        "d" | "delete" => {
+            let (l, r) = range.unwrap_or((row, row));
+            delete_lines(buf, l, r);
+            Ok(ExResult::Message(None))
+        }
+        "w" | "write" => {
+            let message = write(buf, args.trim_start_matches('!').trim())?;
+            Ok(ExResult::Message(Some(message)))
+        }
+        "wq" | "x" => {
+            write(buf, args.trim_start_matches('!').trim())?;
+            Ok(ExResult::Quit)
+        }
+        "q" | "quit" => Ok(ExResult::Quit),
+        "earlier" | "later" => {
+            let is_earlier = name == "earlier";
+            match parse_undo_amount(args.trim())? {
+                UndoAmount::Count(count) if is_earlier => buf.core.earlier(count),
+                UndoAmount::Count(count) => buf.core.later(count),
+                UndoAmount::Seconds(secs) if is_earlier => buf.core.earlier_by_time(secs),
+                UndoAmount::Seconds(secs) => buf.core.later_by_time(secs),
+            }
+            Ok(ExResult::Message(None))
+        }
+        "e" | "edit" => {
+            let path = args.trim_start_matches('!').trim();
+            if path.is_empty() {
+                return Err(err_msg("No file name"));
+            }
+            let path: String = shellexpand::tilde(path).into();
+            buf.open(PathBuf::from(path.clone()));
+            Ok(ExResult::Message(Some(format!("Opened {}", path))))
+        }
+        _ => Err(err_msg(format!("Not an editor command: {}", s))),
+    }
+}
+
+enum UndoAmount {
+    Count(usize),
+    Seconds(u64),
+}
+
+// `5`, `30s`, `10m`, `2h` or `1d` of `:earlier`
+fn parse_undo_amount(s: &str) -> Result<UndoAmount, failure::Error> {
+    if s.is_empty() {
+        return Ok(UndoAmount::Count(1));
+    }
+    let mut rest = s;
+    let n = parse_number(&mut rest).ok_or_else(|| err_msg(format!("Invalid argument: {}", s)))?;
+    let unit = match rest {
+        "" => return Ok(UndoAmount::Count(n)),
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(err_msg(format!("Invalid argument: {}", s))),
+    };
+    Ok(UndoAmount::Seconds((n as u64).saturating_mul(unit)))
+}
+
+fn parse_number(s: &mut &str) -> Option<usize> {
+    let len = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    if len == 0 {
+        return None;
+    }
+    let n = s[..len].parse().unwrap_or(usize::MAX);
+    *s = &s[len..];
+    Some(n)
+}
+
+// Line like `12`, `.`, `$` or `'<` followed by offsets like `+2`. Returns a 0-indexed row.
+fn parse_address(buf: &Buffer, s: &mut &str) -> Result<Option<usize>, failure::Error> {
+    let len_lines = buf.core.buffer().len_lines();
+    let current = buf.core.cursor().row;
+    let mut row = if s.starts_with('.') {
+        *s = &s[1..];
+        Some(current)
+    } else if s.starts_with('$') {
+        *s = &s[1..];
+        Some(len_lines - 1)
+    } else if s.starts_with("'<") || s.starts_with("'>") {
+        let range = buf
+            .last_visual
+            .ok_or_else(|| err_msg("No visual selection"))?;
+        let row = if s.starts_with("'<") {
+            range.l().row
+        } else {
+            range.r().row
+        };
+        *s = &s[2..];
+        Some(row)
+    } else if s.starts_with('\'') {
+        // Marks like `'a`
+        let name = s[1..].chars().next().unwrap_or(' ');
+        let cursor = buf
+            .core
+            .mark(name)
+            .ok_or_else(|| err_msg(format!("Mark not set: {}", name)))?;
+        *s = &s[1 + name.len_utf8()..];
+        Some(cursor.row)
+    } else {
+        parse_number(s).map(|n| min(n, len_lines).saturating_sub(1))
+    };
+
+    loop {
+        let is_plus = if s.starts_with('+') {
+            true
+        } else if s.starts_with('-') {
+            false
+        } else {
+            break;
+        };
+        *s = &s[1..];
+        let n = min(parse_number(s).unwrap_or(1), len_lines);
+        let base = row.unwrap_or(current);
+        row = Some(if is_plus {
+            base + n
+        } else {
+            base.saturating_sub(n)
+        });
+    }
+
+    Ok(row.map(|row| min(row, len_lines - 1)))
+}
+
+// `%`, `'<,'>`, `n,m` or a single line. None if there is no range.
+fn parse_range(buf: &Buffer, s: &mut &str) -> Result<Option<(usize, usize)>, failure::Error> {
+    if s.starts_with('%') {
+        *s = &s[1..];
+        return Ok(Some((0, buf.core.buffer().len_lines() - 1)));
+    }
+
+    let l = parse_address(buf, s)?;
+    if s.starts_with(',') || s.starts_with(';') {
+        *s = &s[1..];
+        let current = buf.core.cursor().row;
+        let l = l.unwrap_or(current);
+        let r = parse_address(buf, s)?.unwrap_or(current);
+        Ok(Some((min(l, r), std::cmp::max(l, r))))
+    } else {
+        Ok(l.map(|l| (l, l)))
+    }
+}
+
+// Take a part of `/pat/rep/` until the delimiter. `\/` is an escaped delimiter.
+fn take_delimited(s: &mut &str, delimiter: char) -> String {
+    let mut res = String::new();
+    let mut chars = s.char_indices();
+    let mut end = s.len();
+    while let Some((i, c)) = chars.next() {
+        if c == delimiter {
+            end = i + c.len_utf8();
+            break;
+        }
+        if c == '\\' {
+            match chars.next() {
+                Some((_, d)) if d == delimiter => res.push(d),
+                Some((_, d)) => {
+                    res.push('\\');
+                    res.push(d);
+                }
+                None => res.push('\\'),
+            }
+        } else {
+            res.push(c);
+        }
+    }
+    *s = &s[end..];
+    res
+}
+
+fn take_delimiter(s: &mut &str) -> Result<char, failure::Error> {
+    let delimiter = s
+        .chars()
+        .next()
+        .filter(|&c| !c.is_alphanumeric() && !c.is_whitespace() && c != '\\')
+        .ok_or_else(|| err_msg("Invalid delimiter"))?;
+    *s = &s[delimiter.len_utf8()..];
+    Ok(delimiter)
+}
+
+// Empty pattern means the last search
+fn build_regex(
+    buf: &Buffer,
+    pattern: &str,
+    ignore_case: Option<bool>,
+) -> Result<Regex, failure::Error> {
+    let pattern = if pattern.is_empty() {
+        buf.search()
+    } else {
+        pattern
+    };
+    if pattern.is_empty() {
+        return Err(err_msg("No previous pattern"));
+    }
+    // Smart case like `/`
+    let case_insensitive = ignore_case.unwrap_or_else(|| !pattern.chars().any(char::is_uppercase));
+    RegexBuilder::new(pattern)
+        .case_insensitive(case_insensitive)
+        .build()
+        .map_err(|_| err_msg(format!("Invalid pattern: {}", pattern)))
+}
+
+// Convert Vim style replacement like `\1-&` to the syntax of regex crate
+fn replacement(rep: &str) -> String {
+    let mut res = String::new();
+    let mut chars = rep.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => res.push_str("${0}"),
+            '$' => res.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(d) if d.is_ascii_digit() => {
+                    res.push_str("${");
+                    res.push(d);
+                    res.push('}');
+                }
+                Some('n') | Some('r') => res.push('\n'),
+                Some('t') => res.push('\t'),
+                Some('$') => res.push_str("$$"),
+                Some(d) => res.push(d),
+                None => res.push('\\'),
+            },
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+// :s/pat/rep/flags. Returns the number of substitutions and lines.
+fn substitute(
+    buf: &mut Buffer,
+    (l, r): (usize, usize),
+    args: &str,
+) -> Result<(usize, usize), failure::Error> {
+    let mut s = args;
+    let delimiter = take_delimiter(&mut s)?;
+    let pattern = take_delimited(&mut s, delimiter);
+    let rep = replacement(&take_delimited(&mut s, delimiter));
+
+    let mut is_global = false;
+    let mut ignore_case = None;
+    for c in s.trim().chars() {
+        match c {
+            'g' => is_global = true,
+            'i' => ignore_case = Some(true),
+            'I' => ignore_case = Some(false),
+            _ => return Err(err_msg(format!("Invalid flag: {}", c))),
+        }
+    }
+    let regex = build_regex(buf, &pattern, ignore_case)?;
+
+    let mut count = 0;
+    let mut lines = 0;
+    let mut last_row = None;
+    // From the bottom so that replacements don't move rows to edit
+    for row in (l..=r).rev() {
+        let line = String::from(buf.core.buffer().l(row));
+        let mut replaces = Vec::new();
+        for caps in regex.captures_iter(&line) {
+            let m = caps.get(0).unwrap();
+            let mut replaced = String::new();
+            caps.expand(&rep, &mut replaced);
+            replaces.push((m.start(), m.end(), replaced));
+            if !is_global {
+                break;
+            }
+        }
+        if replaces.is_empty() {
+            continue;
+        }
+
+        count += replaces.len();
+        lines += 1;
+        last_row.get_or_insert(row);
+        for (start, end, replaced) in replaces.into_iter().rev() {
+            let col = line[..start].chars().count();
+            let len = line[start..end].chars().count();
+            if len > 0 {
+                buf.core.delete_range(CursorRange(
+                    Cursor { row, col },
+                    Cursor {
+                        row,
+                        col: col + len - 1,
+                    },
+                ));
+            }
+            buf.core.set_cursor(Cursor { row, col });
+            for c in replaced.chars() {
+                buf.core.insert(c);
+            }
+        }
+    }
+
+    if let Some(row) = last_row {
+        buf.core.set_cursor(Cursor { row, col: 0 });
+    }
+    Ok((count, lines))
+}
+
+// :g/pat/cmd runs cmd on each matched line
+fn global(
+    buf: &mut Buffer,
+    range: Option<(usize, usize)>,
+    args: &str,
+    invert: bool,
+) -> Result<ExResult, failure::Error> {
+    let (l, r) = range.unwrap_or((0, buf.core.buffer().len_lines() - 1));
+    let mut s = args;
+    let invert = if s.starts_with('!') {
+        s = &s[1..];
+        !invert
+    } else {
+        invert
+    };
+    let delimiter = take_delimiter(&mut s)?;
+    let pattern = take_delimited(&mut s, delimiter);
+    let command = s.trim();
+    if command.is_empty() {
+        return Err(err_msg("Missing command"));
+    }
+    let regex = build_regex(buf, &pattern, None)?;
+
+    // Mark lines first because the command may delete or insert lines
+    let rows: Vec<usize> = (l..=r)
+        .filter(|&row| regex.is_match(&Cow::from(buf.core.buffer().l(row))) != invert)
+        .collect();
+    if rows.is_empty() {
+        return Err(err_msg("Pattern not found"));
+    }
+
+    let mut offset = 0isize;
+    for row in rows {
+        let len_lines = buf.core.buffer().len_lines();
+        let row = row as isize + offset;
+        if row < 0 || row >= len_lines as isize {
+            continue;
+        }
+        buf.core.set_cursor(Cursor {
+            row: row as usize,
+            col: 0,
+        });
+        if let ExResult::Quit = run(buf, command, true)? {
+            return Ok(ExResult::Quit);
+        }
+        offset += buf.core.buffer().len_lines() as isize - len_lines as isize;
+    }
+    Ok(ExResult::Message(None))
+}
+
+fn delete_lines(buf: &mut Buffer, l: usize, r: usize) {
+    let last = buf.core.buffer().len_lines() - 1;
+    let end = Cursor {
+        row: r,
+        col: buf.core.buffer().l(r).len_chars(),
+    };
+    let content = String::from(
+        buf.core
+            .get_slice_by_range(CursorRange(Cursor { row: l, col: 0 }, end)),
+    );
+    let _ = buf.registers.delete(Yank {
+        insert_newline: true,
+        block: false,
+        content: content.trim_end_matches(&['\n', '\r'][..]).into(),
+    });
+
+    // Remove the line end before the last line
+    let start = if r == last && l > 0 {
+        Cursor {
+            row: l - 1,
+            col: buf.core.buffer().l(l - 1).len_chars(),
+        }
+    } else {
+        Cursor { row: l, col: 0 }
+    };
+    buf.core.delete_range(CursorRange(start, end));
+    let row = min(l, buf.core.buffer().len_lines() - 1);
+    buf.core.set_cursor(Cursor { row, col: 0 });
+}
+
+const FILTER_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Replace lines with the output of a shell command given them as the input
+fn filter(buf: &mut Buffer, l: usize, r: usize, command: &str) -> Result<usize, failure::Error> {
+    let len = buf.core.buffer().l(r).len_chars();
+    let mut input = String::from(buf.core.get_slice_by_range(CursorRange(
+        Cursor { row: l, col: 0 },
+        Cursor { row: r, col: len },
+    )));
+    if !input.ends_with('\n') {
+        input.push('\n');
+    }
+
+    let mut child = process::Command::new("sh");
+    child.arg("-c").arg(command);
+    // Kill a hanging command not to freeze the editor
+    let limits = Limits {
+        time: FILTER_TIMEOUT,
+        memory: None,
+    };
+    let execution = test_case::execute(child, input.into_bytes(), &limits)?;
+    if execution.timed_out {
+        return Err(err_msg(format!("Timed out: {}", command.trim())));
+    }
+    if !execution.usage.status.success() {
+        return Err(err_msg(
+            execution
+                .stderr
+                .lines()
+                .next()
+                .map(String::from)
+                .unwrap_or_else(|| format!("Failed to run {}", command.trim())),
+        ));
+    }
+    let output = execution.stdout;
+    let output = output.strip_suffix('\n').unwrap_or(&output);
+
+    // Text from the start of `l` to the end of `r`
+    let end = if len > 0 {
+        Some(Cursor {
+            row: r,
+            col: len - 1,
+        })
+    } else if r > l {
+        Some(Cursor {
+            row: r - 1,
+            col: buf.core.buffer().l(r - 1).len_chars(),
+        })
+    } else {
+        None
+    };
+    if let Some(end) = end {
+        buf.core
+            .delete_range(CursorRange(Cursor { row: l, col: 0 }, end));
+    }
+    buf.core.set_cursor(Cursor { row: l, col: 0 });
+    for c in output.chars() {
+        buf.core.insert(c);
+    }
+    buf.core.set_cursor(Cursor { row: l, col: 0 });
+    Ok(r - l + 1)
+}
+
+fn write(buf: &mut Buffer, path: &str) -> Result<String, failure::Error> {
+    if !path.is_empty() {
+        let path: String = shellexpand::tilde(path).into();
+        buf.set_path(PathBuf::from(path));
+    }
+    let path = buf
+        .path()
+        .map(|p| p.to_string_lossy().into_owned())
+        .ok_or_else(|| err_msg("No file name"))?;
+    buf.format();
+    if buf.save(false) {
+        Ok(format!("Saved to {}", path))
+    } else {
+        Err(err_msg(format!("Failed to save {}", path)))
+    }
+}
@@ -1,13 +1,79 @@
-use crate::parenthesis;
-
-pub fn next_indent_level(line: &str, indent_width: usize) -> usize {
-    let base = line.chars().take_while(|&c| c == ' ').count() / indent_width;
-    if parenthesis::PARENTHESIS_LEFTS
-        .iter()
-        .any(|&c| line.ends_with(c))
-    {
-        base + 1
+use regex::Regex;
+
+// Indentation rules like `indentationRules` of VSCode. The line after a line matching `increase`
+// is indented deeper and a line matching `decrease` is dedented.
+#[derive(Debug, Clone)]
+pub struct IndentRules {
+    increase: Regex,
+    decrease: Regex,
+}
+
+impl Default for IndentRules {
+    fn default() -> Self {
+        Self::new(r"[\{\(\[]\s*$", r"^\s*[\}\)\]]").unwrap()
+    }
+}
+
+impl IndentRules {
+    pub fn new(increase: &str, decrease: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            increase: Regex::new(increase)?,
+            decrease: Regex::new(decrease)?,
+        })
+    }
+
+    pub fn is_decrease(&self, line: &str) -> bool {
+        self.decrease.is_match(line)
+    }
+
+    // Indent width of the line after `prev`
+    pub fn next_indent(&self, prev: &str, indent_width: usize) -> usize {
+        let base = indent_width_of(prev, indent_width);
+        if self.increase.is_match(prev) {
+            base - base % indent_width + indent_width
+        } else {
+            base
+        }
+    }
+
+    // Indent width of `line` after `prev`
+    pub fn indent(&self, prev: &str, line: &str, indent_width: usize) -> usize {
+        let indent = self.next_indent(prev, indent_width);
+        if self.is_decrease(line) {
+            indent.saturating_sub(indent_width)
+        } else {
+            indent
+        }
+    }
+}
+
+// Width of the leading whitespace. A tab goes to the next multiple of `indent_width`.
+pub fn indent_width_of(line: &str, indent_width: usize) -> usize {
+    let mut width = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => width += 1,
+            '\t' => width += indent_width - width % indent_width,
+            _ => break,
+        }
+    }
+    width
+}
+
+// Whitespace for an indent of `width` columns
+pub fn indent_string(width: usize, indent_width: usize, expand_tab: bool) -> String {
+    if expand_tab {
+        " ".repeat(width)
     } else {
-        base
+        format!(
+            "{}{}",
+            "\t".repeat(width / indent_width),
+            " ".repeat(width % indent_width)
+        )
     }
 }
+
+// Number of the leading whitespace chars
+pub fn indent_len(line: &str) -> usize {
+    line.chars().take_while(|&c| c == ' ' || c == '\t').count()
+}
@@ -0,0 +1,191 @@
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::stress;
+use crate::test_case::{self, Limits, Verdict, Waiter};
+
+// The interactor of `a.rs` is `a_interactor.rs`. It talks with the solution by stdin and stdout
+// and exits with 0 to accept it.
+pub fn interactor(path: &Path) -> Option<PathBuf> {
+    stress::sibling(path, "_interactor")
+}
+
+pub struct Report {
+    pub verdict: Verdict,
+    pub time: Duration,
+    pub memory: Option<u64>,
+    pub message: String,
+}
+
+pub enum Transcript {
+    // Written by the solution to the interactor
+    Solution(String),
+    // Written by the interactor to the solution
+    Interactor(String),
+    SolutionStderr(String),
+    InteractorStderr(String),
+    Done(Report),
+    // Failed to start like a compile error of the interactor
+    Error(String),
+}
+
+// Copy output `from` a program `to` the other as soon as it is read like a pipe. Lines are logged
+// separately. Returns the last line.
+fn relay<R, W>(
+    from: Option<R>,
+    mut to: W,
+    tx: mpsc::Sender<Transcript>,
+    tag: fn(String) -> Transcript,
+) -> thread::JoinHandle<Option<String>>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut from = from?;
+        let mut last = None;
+        let mut buf = [0; 4096];
+        // Bytes of the line not ended yet
+        let mut line = Vec::new();
+        let mut log = |line: &[u8]| {
+            let text = String::from_utf8_lossy(line)
+                .trim_end_matches(['\n', '\r'])
+                .to_string();
+            let _ = tx.send(tag(text.clone()));
+            last = Some(text);
+        };
+        loop {
+            let n = match from.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            // Log before the other replies to keep the order of the transcript
+            line.extend_from_slice(&buf[..n]);
+            while let Some(i) = line.iter().position(|&b| b == b'\n') {
+                log(&line[..=i]);
+                line.drain(..=i);
+            }
+            // Keep reading to log after the other exits
+            let _ = to.write_all(&buf[..n]).and_then(|_| to.flush());
+        }
+        if !line.is_empty() {
+            log(&line);
+        }
+        last
+    })
+}
+
+fn spawn(mut command: process::Command) -> io::Result<process::Child> {
+    command
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()
+}
+
+// Run the solution with its stdin and stdout connected to the interactor's
+pub fn run(
+    solution: &Path,
+    interactor: &Path,
+    limits: &Limits,
+    tx: &mpsc::Sender<Transcript>,
+) -> io::Result<Report> {
+    let start = Instant::now();
+    let mut interactor = spawn(process::Command::new(interactor))?;
+    let (interactor_stdin, interactor_stdout, interactor_stderr) = (
+        interactor.stdin.take(),
+        interactor.stdout.take(),
+        interactor.stderr.take(),
+    );
+    let interactor_waiter = Waiter::new(interactor);
+    let mut solution = match spawn(test_case::command(solution, limits)) {
+        Ok(solution) => solution,
+        Err(e) => {
+            interactor_waiter.kill();
+            return Err(e);
+        }
+    };
+
+    let relays = vec![
+        relay(
+            solution.stdout.take(),
+            interactor_stdin.unwrap(),
+            tx.clone(),
+            Transcript::Solution,
+        ),
+        relay(
+            interactor_stdout,
+            solution.stdin.take().unwrap(),
+            tx.clone(),
+            Transcript::Interactor,
+        ),
+        relay(
+            solution.stderr.take(),
+            io::sink(),
+            tx.clone(),
+            Transcript::SolutionStderr,
+        ),
+    ];
+    let interactor_stderr = relay(
+        interactor_stderr,
+        io::sink(),
+        tx.clone(),
+        Transcript::InteractorStderr,
+    );
+    let solution_waiter = Waiter::new(solution);
+
+    let (timed_out, usage) = match solution_waiter.recv_timeout(limits.time) {
+        Some(usage) => (false, usage?),
+        None => {
+            solution_waiter.kill();
+            (true, solution_waiter.recv()?)
+        }
+    };
+    let time = start.elapsed();
+    let interactor_status = match interactor_waiter.recv_timeout(limits.time) {
+        Some(usage) => Some(usage?.status),
+        None => {
+            interactor_waiter.kill();
+            let _ = interactor_waiter.recv();
+            None
+        }
+    };
+    // Don't wait for pipes kept open by killed programs
+    let message = if timed_out || interactor_status.is_none() {
+        None
+    } else {
+        for relay in relays.into_iter() {
+            let _ = relay.join();
+        }
+        interactor_stderr.join().ok().and_then(|last| last)
+    };
+
+    let verdict = if timed_out {
+        Verdict::TLE
+    } else if let Some(verdict) = test_case::limit_verdict(&usage, limits, "") {
+        verdict
+    } else {
+        match interactor_status {
+            Some(status) if !status.success() => Verdict::WA,
+            None => Verdict::WA,
+            _ if !usage.status.success() => Verdict::RE,
+            _ => Verdict::AC,
+        }
+    };
+    let message = match interactor_status {
+        None => "The interactor did not finish".to_string(),
+        Some(status) => message.unwrap_or_else(|| format!("The interactor {}", status)),
+    };
+    Ok(Report {
+        verdict,
+        time,
+        memory: usage.memory,
+        message,
+    })
+}
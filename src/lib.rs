@@ -8,19 +8,27 @@ mod core;
 mod cursor;
 pub mod draw;
 mod draw_cache;
+mod ex;
 mod formatter;
 mod indent;
+pub mod interactive;
 mod job_queue;
 mod lsp;
 mod mode;
+mod operator;
 pub mod parenthesis;
+mod register;
 mod rmate;
 mod ropey_util;
 mod rustc;
+mod search;
 pub mod storage;
+pub mod stress;
 pub mod syntax;
+pub mod test_case;
 mod text_object;
 pub mod theme;
+mod undo_file;
 
 pub use buffer::Buffer;
 pub use buffer_mode::BufferMode;
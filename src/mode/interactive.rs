@@ -0,0 +1,174 @@
+use std::cmp::min;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+
+use termion::event::{Event, Key};
+
+use super::{Mode, Transition};
+use crate::buffer::Buffer;
+use crate::config::types::keys;
+use crate::draw;
+use crate::interactive::{self, Report, Transcript};
+use crate::stress;
+use crate::test_case::{self, Verdict};
+
+// Runs the buffer with its interactor and shows the transcript
+pub struct Interactive {
+    path: PathBuf,
+    // Direction markers and lines
+    lines: Vec<(&'static str, String, draw::CharStyle)>,
+    row_offset: usize,
+    running: Option<mpsc::Receiver<Transcript>>,
+    report: Option<Report>,
+    message: String,
+}
+
+impl Interactive {
+    pub fn new(buf: &Buffer) -> Option<Self> {
+        Some(Self {
+            path: buf.path()?.to_path_buf(),
+            lines: Vec::new(),
+            row_offset: 0,
+            running: None,
+            report: None,
+            message: String::new(),
+        })
+    }
+
+    pub fn start(&mut self, buf: &mut Buffer) {
+        self.lines.clear();
+        self.row_offset = 0;
+        self.running = None;
+        self.report = None;
+
+        buf.format();
+        buf.save(true);
+        buf.wait_compile_message();
+        if buf.last_compile_success() == Some(false) {
+            self.message = "Compile error".into();
+            return;
+        }
+        let path = self.path.clone();
+
+        self.message = "Running...".into();
+        let config = buf.get_config::<keys::Compiler>().cloned();
+        let limits = buf.limits();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            // Build the interactor here not to block the UI
+            let programs = (|| -> Result<(PathBuf, PathBuf), String> {
+                Ok((
+                    test_case::program(&path).ok_or("Failed to run")?,
+                    stress::build(
+                        config.as_ref(),
+                        &interactive::interactor(&path).ok_or("Failed to run")?,
+                    )?,
+                ))
+            })();
+            let (solution, interactor) = match programs {
+                Ok(programs) => programs,
+                Err(e) => {
+                    let _ = tx.send(Transcript::Error(e));
+                    return;
+                }
+            };
+            let report = interactive::run(&solution, &interactor, &limits, &tx);
+            let report = report.unwrap_or_else(|e| Report {
+                verdict: Verdict::RE,
+                time: Default::default(),
+                memory: None,
+                message: e.to_string(),
+            });
+            let _ = tx.send(Transcript::Done(report));
+        });
+        self.running = Some(rx);
+    }
+}
+
+impl Mode for Interactive {
+    fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        match event {
+            Event::Key(Key::Esc) => return Transition::Return(None, false),
+            Event::Key(Key::Char('r')) => self.start(buf),
+            Event::Key(Key::Char('j')) | Event::Key(Key::Down) => {
+                self.row_offset = min(self.row_offset + 1, self.lines.len().saturating_sub(1));
+            }
+            Event::Key(Key::Char('k')) | Event::Key(Key::Up) => {
+                self.row_offset = self.row_offset.saturating_sub(1);
+            }
+            _ => {}
+        }
+        Transition::Nothing
+    }
+
+    fn draw(&mut self, _buf: &mut Buffer, mut view: draw::TermView) -> draw::CursorState {
+        let height = view.height();
+        let width = view.width();
+        let body = height.saturating_sub(2);
+        // Follow the end unless scrolled up
+        let is_following = self.row_offset + body >= self.lines.len();
+        if let Some(running) = self.running.as_ref() {
+            while let Ok(transcript) = running.try_recv() {
+                let line = match transcript {
+                    Transcript::Solution(s) => ("-> ", s, draw::styles::DEFAULT),
+                    Transcript::Interactor(s) => ("<- ", s, draw::styles::SELECTED),
+                    Transcript::SolutionStderr(s) => ("!  ", s, draw::styles::UI),
+                    Transcript::InteractorStderr(s) => ("#  ", s, draw::styles::UI),
+                    Transcript::Done(report) => {
+                        self.message = report.message.clone();
+                        self.report = Some(report);
+                        continue;
+                    }
+                    Transcript::Error(e) => {
+                        self.message = e;
+                        continue;
+                    }
+                };
+                self.lines.push(line);
+            }
+        }
+        if is_following {
+            self.row_offset = self.lines.len().saturating_sub(body);
+        }
+
+        {
+            let mut view = view.view((0, 0), body, width);
+            for (marker, line, style) in &self.lines[self.row_offset..] {
+                view.puts(marker, draw::styles::UI);
+                view.puts(line, *style);
+                view.newline();
+            }
+        }
+        {
+            let mut status = view.view((height - 2, 0), 1, width);
+            if let Some(report) = self.report.as_ref() {
+                let style = if report.verdict == Verdict::AC {
+                    draw::styles::ACCEPTED
+                } else {
+                    draw::styles::HIGHLIGHT
+                };
+                status.puts(&format!("{} ", report.verdict.as_str()), style);
+                status.puts(
+                    &format!("{} ms ", report.time.as_millis()),
+                    draw::styles::DEFAULT,
+                );
+                if let Some(memory) = report.memory {
+                    status.puts(
+                        &format!("{:.1} MB ", memory as f64 / (1 << 20) as f64),
+                        draw::styles::DEFAULT,
+                    );
+                }
+            }
+            status.puts(&self.message, draw::styles::DEFAULT);
+        }
+        {
+            let mut footer = view.view((height - 1, 0), 1, width);
+            footer.puts(
+                "[-> Solution] [<- Interactor] [! # stderr] [j/k: Scroll] [r: Restart] [Esc: Return]",
+                draw::styles::FOOTER,
+            );
+        }
+        draw::CursorState::Hide
+    }
+}
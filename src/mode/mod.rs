@@ -1,7 +1,6 @@
 use std;
 use std::borrow::Cow;
 use std::cmp::{max, min};
-use std::ffi::OsString;
 use std::io::BufRead;
 use std::io::BufReader;
 use std::io::Write;
@@ -11,33 +10,43 @@ use std::sync::mpsc;
 use std::thread;
 use std::time::Instant;
 
-use aho_corasick::Automaton;
 use ropey::Rope;
 use shellexpand;
 use termion;
 use termion::event::{Event, Key, MouseButton, MouseEvent};
 
 use crate::buffer::Buffer;
-use crate::buffer::Yank;
 use crate::clipboard;
 use crate::core::Core;
 use crate::core::Cursor;
 use crate::core::CursorRange;
 use crate::core::Id;
 use crate::draw;
+use crate::ex::{self, ExResult};
 use crate::indent;
+use crate::operator;
 use crate::parenthesis;
+use crate::register::{Registers, Yank};
 use crate::ropey_util::RopeExt;
 use crate::ropey_util::RopeSliceExt;
-use crate::text_object::{self, Action};
+use crate::search;
+use crate::test_case::{Limits, Verdict, Waiter};
+use crate::text_object::{self, lines_range, Action};
 
 mod fuzzy;
+mod interactive;
+mod stress;
+mod test_case;
 
 pub enum Transition {
     Nothing,
     Trans(Box<Mode>),
-    RecordMacro(Box<Mode>),
-    DoMacro,
+    // Mode, keys typed before the command
+    RecordMacro(Box<Mode>, CommandPrefix),
+    // Count to override the recorded one
+    DoMacro(Option<usize>),
+    // Events of a macro recorded by `q`
+    PlayMacro(Vec<Event>),
     // Message, is commit dot macro?
     Return(Option<String>, bool),
     Exit,
@@ -45,6 +54,12 @@ pub enum Transition {
     // 1-indexed
     ChangeTab(usize),
     StartRmate,
+    // `mA` sets a mark in this buffer. Remove it from other tabs.
+    SetGlobalMark(char),
+    // Jump to a mark in another tab. Is linewise like `'A`?
+    JumpGlobalMark(char, bool),
+    // Open a file in a new tab and keep this mode
+    OpenNewTab(PathBuf),
 }
 
 impl<T: Mode + 'static> From<T> for Transition {
@@ -53,15 +68,106 @@ impl<T: Mode + 'static> From<T> for Transition {
     }
 }
 
+// Keys typed before a command like `"a3` of `"a3dw`
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CommandPrefix {
+    pub register: Option<char>,
+    pub count: Option<usize>,
+    // `g` of operators like `gu`
+    pub g: bool,
+}
+
+impl CommandPrefix {
+    pub fn events(self) -> Vec<Event> {
+        let mut events = Vec::new();
+        if let Some(register) = self.register {
+            events.push(Event::Key(Key::Char('"')));
+            events.push(Event::Key(Key::Char(register)));
+        }
+        if let Some(count) = self.count {
+            events.extend(count.to_string().chars().map(|c| Event::Key(Key::Char(c))));
+        }
+        if self.g {
+            events.push(Event::Key(Key::Char('g')));
+        }
+        events
+    }
+
+    // Parse a prefix of recorded events. Returns the prefix and its length.
+    pub fn parse(events: &[Event]) -> (Self, usize) {
+        let mut prefix = Self::default();
+        let mut i = 0;
+        if let (Some(Event::Key(Key::Char('"'))), Some(Event::Key(Key::Char(c)))) =
+            (events.first(), events.get(1))
+        {
+            prefix.register = Some(*c);
+            i = 2;
+        }
+        while let Some(Event::Key(Key::Char(c))) = events.get(i) {
+            if let Some(d) = c.to_digit(10) {
+                prefix.count = Some(push_count_digit(prefix.count, d));
+                i += 1;
+            } else {
+                break;
+            }
+        }
+        (prefix, i)
+    }
+}
+
+// Remove the count typed after the operator like `2` of `d2w` from recorded events of the command
+// at `start`. A count given to `.` replaces the whole count of the command like Vim.
+pub fn remove_motion_count(events: &mut Vec<Event>, start: usize) {
+    let mut i = start;
+    let is_g = events.get(i) == Some(&Event::Key(Key::Char('g')));
+    if is_g {
+        i += 1;
+    }
+    let is_operator = match events.get(i) {
+        Some(Event::Key(Key::Char(c))) if is_g => Action::from_g_char(*c).is_some(),
+        Some(Event::Key(Key::Char(c))) => Action::from_char(*c).is_some(),
+        _ => false,
+    };
+    if !is_operator {
+        return;
+    }
+    i += 1;
+    let mut end = i;
+    while let Some(Event::Key(Key::Char(c))) = events.get(end) {
+        // `0` is a motion unless it follows a digit
+        if c.is_ascii_digit() && (*c != '0' || end > i) {
+            end += 1;
+        } else {
+            break;
+        }
+    }
+    events.drain(i..end);
+}
+
 pub trait Mode {
     fn init(&mut self, _buf: &mut Buffer) {}
     fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition;
     fn draw(&mut self, buf: &mut Buffer, view: draw::TermView) -> draw::CursorState;
+    // Called every frame for results of background tasks
+    fn poll(&mut self, _buf: &mut Buffer) -> Transition {
+        Transition::Nothing
+    }
 }
 
 pub struct Normal {
     message: String,
     frame: usize,
+    prefix: CommandPrefix,
+    // After `"`
+    reading_register: bool,
+    // After `q` or `@`
+    reading_macro: Option<char>,
+    // After `g`
+    reading_g: bool,
+    // After `m`, `'` or `` ` ``
+    reading_mark: Option<char>,
+    // After `z`
+    reading_z: bool,
 }
 
 pub struct Completion {
@@ -89,49 +195,311 @@ impl Default for Insert {
     }
 }
 
-struct R;
+struct R {
+    count: usize,
+}
 
 struct S(CursorRange);
 
+// ds, cs and ys like vim-surround
+struct Surround {
+    action: Action,
+    // The pair to be replaced by cs
+    target: Option<char>,
+    // The motion of ys and the range to surround
+    parser: text_object::TextObjectParser,
+    range: Option<CursorRange>,
+}
+
 struct Find {
     to_right: bool,
+    count: usize,
 }
 
 struct TextObjectOperation {
     parser: text_object::TextObjectParser,
+    // Count typed before the operator
+    count: usize,
+    // Count typed after the operator like `d3w`
+    inner_count: Option<usize>,
 }
 
 impl TextObjectOperation {
-    fn new(action: Action) -> Self {
+    fn new(action: Action, count: usize) -> Self {
         Self {
             parser: text_object::TextObjectParser::new(action),
+            count,
+            inner_count: None,
+        }
+    }
+
+    fn count(&self) -> usize {
+        self.count * self.inner_count.unwrap_or(1)
+    }
+
+    // Operators other than d, y and c. None for them.
+    fn operate(&self, buf: &mut Buffer, range: CursorRange) -> Option<Transition> {
+        let rows = range.l().row..=range.r().row;
+        match self.parser.action {
+            Action::Delete | Action::Yank | Action::Change => return None,
+            Action::Filter => {
+                // Like Vim, `!j` opens the command line to type a command
+                let line = format!("{},{}!", range.l().row + 1, range.r().row + 1);
+                return Some(CommandLine { line }.into());
+            }
+            Action::Comment if buf.comment().is_none() => {
+                return Some(Transition::Return(
+                    Some("No comment for this language".into()),
+                    false,
+                ));
+            }
+            _ => {}
+        }
+        buf.core.clear_cursors();
+        match self.parser.action {
+            Action::ShiftRight => operator::shift(buf, rows, true),
+            Action::ShiftLeft => operator::shift(buf, rows, false),
+            Action::Lowercase => operator::change_case(&mut buf.core, range, operator::to_lower),
+            Action::Uppercase => operator::change_case(&mut buf.core, range, operator::to_upper),
+            Action::ToggleCase => {
+                operator::change_case(&mut buf.core, range, operator::toggle_case)
+            }
+            Action::Reindent => operator::reindent(buf, rows),
+            Action::Comment => {
+                if let Some(comment) = buf.comment() {
+                    operator::toggle_comment(buf, rows, comment);
+                }
+            }
+            Action::Rewrap => {
+                let width = buf.text_width();
+                operator::rewrap(buf, rows, width);
+            }
+            _ => {}
+        }
+        buf.core.commit();
+        buf.show_cursor();
+        Some(Transition::Return(None, true))
+    }
+
+    // d, y, c and the other operators over a characterwise range
+    fn operate_chars(&self, buf: &mut Buffer, range: CursorRange) -> Transition {
+        if let Some(t) = self.operate(buf, range) {
+            return t;
+        }
+        let yank = Yank {
+            insert_newline: false,
+            block: false,
+            content: String::from(buf.core.get_slice_by_range(range)),
+        };
+        self.store(buf, yank);
+        match self.parser.action {
+            Action::Delete => {
+                buf.core.delete_range(range);
+                buf.core.commit();
+                Transition::Return(None, true)
+            }
+            Action::Change => {
+                buf.core.delete_range(range);
+                buf.core.commit();
+                Insert::default().into()
+            }
+            Action::Yank => Transition::Return(None, false),
+            _ => unreachable!(),
+        }
+    }
+
+    fn store(&self, buf: &mut Buffer, yank: Yank) {
+        let _ = if self.parser.action == Action::Yank {
+            buf.registers.yank(yank)
+        } else {
+            buf.registers.delete(yank)
+        };
+    }
+}
+
+// Quotes are not paired after a word like `don't` or at lifetimes of Rust
+fn is_pair_position(c: char, before: &str, is_rust: bool) -> bool {
+    if c != '"' && c != '\'' && c != '`' {
+        return true;
+    }
+    if before.chars().last().is_some_and(char::is_alphanumeric) {
+        return false;
+    }
+    if is_rust && c == '\'' {
+        // `&'a`, `<'a>`, `T: 'a`, `'a + 'b` and `<'a, 'b>`
+        let trimmed = before.trim_end();
+        let is_generics = trimmed.matches('<').count() > trimmed.matches('>').count();
+        match trimmed.chars().last() {
+            Some('&') | Some('<') | Some('+') => return false,
+            Some(':') if !trimmed.ends_with("::") => return false,
+            Some(',') if is_generics => return false,
+            _ => {}
         }
     }
+    true
 }
 
-struct Search;
+// Avoid absurd repetitions by a mistyped count
+const MAX_COUNT: usize = 99999;
+
+fn push_count_digit(count: Option<usize>, digit: u32) -> usize {
+    min(count.unwrap_or(0) * 10 + digit as usize, MAX_COUNT)
+}
+
+// Put the lines of a block yank at `col` of successive rows. Short lines are padded with spaces
+// and lines are added at the end of the buffer if needed.
+fn put_block(core: &mut Core, content: &str, col: usize) {
+    let top = core.cursor().row;
+    core.clear_cursors();
+    for (i, line) in content.split('\n').enumerate() {
+        let row = top + i;
+        if row == core.buffer().len_lines() {
+            let col = core.buffer().l(row - 1).len_chars();
+            core.set_cursor(Cursor { row: row - 1, col });
+            core.insert('\n');
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let len = core.buffer().l(row).len_chars();
+        core.set_cursor(Cursor {
+            row,
+            col: min(col, len),
+        });
+        for _ in len..col {
+            core.insert(' ');
+        }
+        for c in line.chars() {
+            core.insert(c);
+        }
+    }
+    let col = min(col, core.buffer().l(top).len_chars());
+    core.set_cursor(Cursor { row: top, col });
+}
+
+// Add a cursor at the next occurrence of the word under the main cursor like `Ctrl-n` of
+// vim-multiple-cursors
+fn add_cursor_at_next_word(buf: &mut Buffer) -> bool {
+    let cursor = buf.core.cursor();
+    let line: Vec<char> = buf.core.current_line().chars().collect();
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    if cursor.col >= line.len() || !is_word(line[cursor.col]) {
+        return false;
+    }
+    let mut l = cursor.col;
+    while l > 0 && is_word(line[l - 1]) {
+        l -= 1;
+    }
+    let mut r = cursor.col;
+    while r < line.len() && is_word(line[r]) {
+        r += 1;
+    }
+    let word: String = line[l..r].iter().collect();
+    let regex = match regex::Regex::new(&format!(r"\b{}\b", regex::escape(&word))) {
+        Ok(regex) => regex,
+        Err(_) => return false,
+    };
+
+    let start = Cursor {
+        row: cursor.row,
+        col: l,
+    };
+    let mut from = start;
+    // Skip matches which already have a cursor
+    for _ in 0..=buf.core.cursors().len() {
+        let next = match search::find_next(buf.core.buffer(), &regex, from) {
+            Some(next) if next != start => next,
+            _ => return false,
+        };
+        let new_cursor = Cursor {
+            row: next.row,
+            col: next.col + cursor.col - l,
+        };
+        if !buf.core.cursors().contains(&new_cursor) {
+            buf.core.add_cursor(new_cursor);
+            return true;
+        }
+        from = next;
+    }
+    false
+}
+
+struct Search {
+    query: String,
+    // To restore on Esc
+    orig_cursor: Cursor,
+    orig_search: String,
+    // None while editing a new query
+    history_index: Option<usize>,
+    // The new query while browsing history
+    draft: String,
+}
+
+impl Search {
+    fn new(buf: &Buffer) -> Self {
+        Self {
+            query: String::new(),
+            orig_cursor: buf.core.cursor(),
+            orig_search: buf.search().to_string(),
+            history_index: None,
+            draft: String::new(),
+        }
+    }
+
+    // Highlight and jump to the first match while typing
+    fn update(&self, buf: &mut Buffer) {
+        buf.core.set_cursor(self.orig_cursor);
+        buf.set_search(self.query.clone());
+        buf.search_next(true);
+        buf.show_cursor();
+    }
+}
 
 struct Save {
     path: String,
 }
 
+// Ex command line started by `:`
+struct CommandLine {
+    line: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum VisualKind {
+    Char,
+    Line,
+    // Rectangle by Ctrl-v
+    Block,
+}
+
 struct Visual {
     cursor: Cursor,
-    line_mode: bool,
+    kind: VisualKind,
+    // After `"`
+    reading_register: bool,
+    // After `g`
+    reading_g: bool,
 }
 
 struct ViewProcess {
     row_offset: usize,
     pub buf: Vec<String>,
-    pub reader: mpsc::Receiver<String>,
-    pub process: process::Child,
+    // Lines and whether they are from stderr
+    pub reader: mpsc::Receiver<(String, bool)>,
+    // Kept apart from stdout to judge MLE by allocation failure messages
+    stderr: String,
     pub start: Instant,
     pub end: Option<Instant>,
+    limits: Limits,
+    waiter: Waiter,
+    memory: Option<u64>,
+    // TLE or MLE
+    verdict: Option<Verdict>,
 }
 
 impl Drop for ViewProcess {
     fn drop(&mut self) {
-        let _ = self.process.kill();
+        self.waiter.kill();
     }
 }
 
@@ -141,10 +509,11 @@ struct Goto {
 }
 
 impl ViewProcess {
-    fn with_process(mut child: process::Child) -> Option<Self> {
+    fn with_process(mut child: process::Child, limits: Limits) -> Option<Self> {
         let now = Instant::now();
         let stdout = child.stdout.take()?;
         let stderr = child.stderr.take()?;
+        let waiter = Waiter::new(child);
         let (tx, rx) = mpsc::channel();
         let tx1 = tx.clone();
         let tx2 = tx.clone();
@@ -155,7 +524,7 @@ impl ViewProcess {
             loop {
                 line.clear();
                 if stdout.read_line(&mut line).is_ok() && !line.is_empty() {
-                    if tx1.send(line.trim_end().to_string()).is_err() {
+                    if tx1.send((line.trim_end().to_string(), false)).is_err() {
                         return;
                     }
                 } else {
@@ -169,7 +538,7 @@ impl ViewProcess {
             loop {
                 line.clear();
                 if stderr.read_line(&mut line).is_ok() && !line.is_empty() {
-                    if tx2.send(line.trim_end().to_string()).is_err() {
+                    if tx2.send((line.trim_end().to_string(), true)).is_err() {
                         return;
                     }
                 } else {
@@ -181,9 +550,13 @@ impl ViewProcess {
             row_offset: 0,
             buf: Vec::new(),
             reader: rx,
-            process: child,
+            stderr: String::new(),
             start: now,
             end: None,
+            limits,
+            waiter,
+            memory: None,
+            verdict: None,
         })
     }
 }
@@ -193,47 +566,252 @@ impl Default for Normal {
         Self {
             message: String::new(),
             frame: 0,
+            prefix: CommandPrefix::default(),
+            reading_register: false,
+            reading_macro: None,
+            reading_g: false,
+            reading_mark: None,
+            reading_z: false,
         }
     }
 }
 
 impl Normal {
     pub fn with_message(message: String) -> Self {
-        Self { message, frame: 0 }
+        Self {
+            message,
+            frame: 0,
+            prefix: CommandPrefix::default(),
+            reading_register: false,
+            reading_macro: None,
+            reading_g: false,
+            reading_mark: None,
+            reading_z: false,
+        }
+    }
+
+    // `ma`, `'a` and `` `a ``. Uppercase marks are shared by tabs.
+    fn mark(&mut self, buf: &mut Buffer, command: char, name: char) -> Transition {
+        if command == 'm' {
+            buf.core.set_mark(name);
+            if name.is_ascii_uppercase() {
+                return Transition::SetGlobalMark(name);
+            }
+            return Transition::Nothing;
+        }
+        let linewise = command == '\'';
+        if let Some(cursor) = buf.core.mark(name) {
+            buf.core.push_jump();
+            buf.core.set_cursor(cursor);
+            if linewise {
+                buf.core.first_non_blank();
+            }
+            buf.show_cursor();
+        } else if name.is_ascii_uppercase() {
+            buf.core.push_jump();
+            return Transition::JumpGlobalMark(name, linewise);
+        } else {
+            self.message = "Mark not set".into();
+        }
+        Transition::Nothing
+    }
+
+    // zz and folds
+    fn z(&mut self, buf: &mut Buffer, c: char) {
+        let found = match c {
+            'z' => {
+                buf.show_cursor_middle();
+                return;
+            }
+            'c' => buf.core.close_fold(),
+            'o' => {
+                buf.core.open_fold();
+                true
+            }
+            'a' => buf.core.open_fold() || buf.core.close_fold(),
+            'M' => {
+                buf.core.close_all_folds();
+                true
+            }
+            'R' => {
+                buf.core.open_all_folds();
+                true
+            }
+            _ => true,
+        };
+        if !found {
+            self.message = "No fold found".into();
+        }
+        buf.show_cursor();
     }
 }
 
 impl Mode for Normal {
     fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        if self.reading_register {
+            self.reading_register = false;
+            if let Event::Key(Key::Char(c)) = event {
+                if Registers::is_valid(c) {
+                    self.prefix.register = Some(c);
+                }
+            }
+            return Transition::Nothing;
+        }
+        if let Some(command) = self.reading_macro.take() {
+            let n = self.prefix.count.unwrap_or(1);
+            self.prefix = CommandPrefix::default();
+            if let Event::Key(Key::Char(c)) = event {
+                if command == 'q' && c.is_ascii_alphabetic() {
+                    buf.registers.start_recording(c);
+                } else if command == '@' && (c.is_ascii_alphabetic() || c == '@') {
+                    if let Some(events) = buf.registers.get_macro(c) {
+                        let len = events.len();
+                        return Transition::PlayMacro(
+                            events.into_iter().cycle().take(len * n).collect(),
+                        );
+                    }
+                }
+            }
+            return Transition::Nothing;
+        }
+        if std::mem::replace(&mut self.reading_z, false) {
+            self.prefix = CommandPrefix::default();
+            if let Event::Key(Key::Char(c)) = event {
+                self.z(buf, c);
+            }
+            return Transition::Nothing;
+        }
+        if let Some(command) = self.reading_mark.take() {
+            self.prefix = CommandPrefix::default();
+            if let Event::Key(Key::Char(c)) = event {
+                if c.is_ascii_alphabetic() {
+                    return self.mark(buf, command, c);
+                }
+            }
+            return Transition::Nothing;
+        }
+        match event {
+            Event::Key(Key::Char(c)) if !self.reading_g => {
+                if c == '"' {
+                    self.reading_register = true;
+                    return Transition::Nothing;
+                }
+                if c == 'q' && buf.registers.recording().is_some() {
+                    buf.registers.stop_recording();
+                    self.prefix = CommandPrefix::default();
+                    return Transition::Nothing;
+                }
+                if c == 'q' || c == '@' {
+                    self.reading_macro = Some(c);
+                    return Transition::Nothing;
+                }
+                if c == 'm' || c == '\'' || c == '`' {
+                    self.reading_mark = Some(c);
+                    return Transition::Nothing;
+                }
+                if c == 'g' {
+                    self.reading_g = true;
+                    return Transition::Nothing;
+                }
+                if c == 'z' {
+                    self.reading_z = true;
+                    return Transition::Nothing;
+                }
+                if let Some(d) = c.to_digit(10) {
+                    // `0` is a motion unless a count is being typed
+                    if d != 0 || self.prefix.count.is_some() {
+                        self.prefix.count = Some(push_count_digit(self.prefix.count, d));
+                        return Transition::Nothing;
+                    }
+                }
+            }
+            _ => {}
+        }
+        let prefix = std::mem::take(&mut self.prefix);
+        buf.registers.select(prefix.register);
+        let count = prefix.count;
+        let n = count.unwrap_or(1);
+
+        if std::mem::replace(&mut self.reading_g, false) {
+            match event {
+                Event::Key(Key::Char('g')) => {
+                    let row = min(count.unwrap_or(1) - 1, buf.core.buffer().len_lines() - 1);
+                    buf.core.push_jump();
+                    buf.core.set_cursor(Cursor { row, col: 0 });
+                }
+                Event::Key(Key::Char('-')) => buf.core.earlier(n),
+                Event::Key(Key::Char('+')) => buf.core.later(n),
+                Event::Key(Key::Char(c)) => {
+                    if let Some(action) = Action::from_g_char(c) {
+                        return Transition::RecordMacro(
+                            Box::new(TextObjectOperation::new(action, n)),
+                            CommandPrefix { g: true, ..prefix },
+                        );
+                    }
+                }
+                _ => {}
+            }
+            buf.show_cursor();
+            return Transition::Nothing;
+        }
+
         match event {
+            Event::Key(Key::Esc) => {
+                buf.core.clear_cursors();
+            }
+            Event::Key(Key::Ctrl('o')) => {
+                for _ in 0..n {
+                    buf.core.jump_older();
+                }
+                buf.show_cursor();
+            }
+            // Ctrl-i
+            Event::Key(Key::Char('\t')) => {
+                for _ in 0..n {
+                    buf.core.jump_newer();
+                }
+                buf.show_cursor();
+            }
+            Event::Key(Key::Ctrl('n')) => {
+                if !add_cursor_at_next_word(buf) {
+                    self.message = "No other match".into();
+                }
+                buf.show_cursor();
+            }
             Event::Key(Key::Char('.')) => {
-                return Transition::DoMacro;
+                return Transition::DoMacro(count);
             }
             Event::Key(Key::Char('u')) => {
-                buf.core.undo();
+                for _ in 0..n {
+                    buf.core.undo();
+                }
                 buf.show_cursor();
             }
             Event::Key(Key::Char('U')) => {
-                buf.core.redo();
+                for _ in 0..n {
+                    buf.core.redo();
+                }
                 buf.show_cursor();
             }
             Event::Key(Key::Char('i')) => {
                 buf.show_cursor();
-                return Transition::RecordMacro(Box::new(Insert::default()));
+                return Transition::RecordMacro(Box::new(Insert::default()), prefix);
             }
             Event::Key(Key::Char('I')) => {
-                let mut i = 0;
-                {
-                    let line = buf.core.current_line();
-                    while i < line.len_chars() && line.char(i) == ' ' {
-                        i += 1;
+                buf.core.for_each_cursor(|core| {
+                    let mut i = 0;
+                    {
+                        let line = core.current_line();
+                        while i < line.len_chars() && line.char(i) == ' ' {
+                            i += 1;
+                        }
                     }
-                }
-                let mut c = buf.core.cursor();
-                c.col = i;
-                buf.core.set_cursor(c);
+                    let mut c = core.cursor();
+                    c.col = i;
+                    core.set_cursor(c);
+                });
                 buf.show_cursor();
-                return Transition::RecordMacro(Box::new(Insert::default()));
+                return Transition::RecordMacro(Box::new(Insert::default()), prefix);
             }
             Event::Key(Key::Char('S')) => {
                 let mut c = buf.core.cursor();
@@ -243,80 +821,128 @@ impl Mode for Normal {
                     buf.core.delete()
                 }
                 buf.indent();
-                return Transition::RecordMacro(Box::new(Insert::default()));
+                return Transition::RecordMacro(Box::new(Insert::default()), prefix);
             }
             Event::Key(Key::Char('C')) => {
-                while buf.core.char_at_cursor().is_some() {
-                    buf.core.delete()
-                }
-                return Transition::RecordMacro(Box::new(Insert::default()));
+                buf.core.for_each_cursor(|core| {
+                    while core.char_at_cursor().is_some() {
+                        core.delete()
+                    }
+                });
+                return Transition::RecordMacro(Box::new(Insert::default()), prefix);
             }
             Event::Key(Key::Char('a')) => {
-                buf.core.cursor_right();
+                buf.core.for_each_cursor(Core::cursor_right);
                 buf.show_cursor();
-                return Transition::RecordMacro(Box::new(Insert::default()));
+                return Transition::RecordMacro(Box::new(Insert::default()), prefix);
             }
             Event::Key(Key::Char('A')) => {
-                let mut c = buf.core.cursor();
-                c.col = buf.core.current_line().len_chars();
-                buf.core.set_cursor(c);
+                buf.core.for_each_cursor(|core| {
+                    let mut c = core.cursor();
+                    c.col = core.current_line().len_chars();
+                    core.set_cursor(c);
+                });
                 buf.show_cursor();
-                return Transition::RecordMacro(Box::new(Insert::default()));
+                return Transition::RecordMacro(Box::new(Insert::default()), prefix);
             }
             Event::Key(Key::Char('r')) => {
                 buf.show_cursor();
-                return Transition::RecordMacro(Box::new(R));
+                return Transition::RecordMacro(Box::new(R { count: n }), prefix);
             }
             Event::Key(Key::Char('s')) => {
-                buf.core.delete();
+                buf.core.for_each_cursor(Core::delete);
                 buf.show_cursor();
-                return Transition::RecordMacro(Box::new(Insert::default()));
+                return Transition::RecordMacro(Box::new(Insert::default()), prefix);
             }
             Event::Key(Key::Char('o')) => {
-                buf.core.insert_newline();
-                buf.indent();
+                let (indent_width, expand_tab) = (buf.indent_width(), buf.expand_tab());
+                let rules = buf.indent_rules().clone();
+                buf.core.for_each_cursor(|core| {
+                    core.insert_newline();
+                    core.indent(&rules, indent_width, expand_tab);
+                });
                 buf.show_cursor();
-                return Transition::RecordMacro(Box::new(Insert::default()));
+                return Transition::RecordMacro(Box::new(Insert::default()), prefix);
             }
             Event::Key(Key::Char('O')) => {
-                buf.core.insert_newline_here();
-                buf.indent();
+                let (indent_width, expand_tab) = (buf.indent_width(), buf.expand_tab());
+                let rules = buf.indent_rules().clone();
+                buf.core.for_each_cursor(|core| {
+                    core.insert_newline_here();
+                    core.indent(&rules, indent_width, expand_tab);
+                });
                 buf.show_cursor();
-                return Transition::RecordMacro(Box::new(Insert::default()));
+                return Transition::RecordMacro(Box::new(Insert::default()), prefix);
             }
             Event::Key(Key::Char('h')) => {
-                buf.core.cursor_left();
+                buf.core.for_each_cursor(|core| {
+                    for _ in 0..n {
+                        core.cursor_left();
+                    }
+                });
                 buf.show_cursor();
             }
             Event::Key(Key::Char('j')) => {
-                buf.core.cursor_down();
+                buf.core.for_each_cursor(|core| {
+                    for _ in 0..n {
+                        core.cursor_down();
+                    }
+                });
                 buf.show_cursor();
             }
             Event::Key(Key::Char('k')) => {
-                buf.core.cursor_up();
+                buf.core.for_each_cursor(|core| {
+                    for _ in 0..n {
+                        core.cursor_up();
+                    }
+                });
                 buf.show_cursor();
             }
             Event::Key(Key::Char('l')) => {
-                buf.core.cursor_right();
+                buf.core.for_each_cursor(|core| {
+                    for _ in 0..n {
+                        core.cursor_right();
+                    }
+                });
                 buf.show_cursor();
             }
             Event::Key(Key::Char('w')) => {
-                buf.core.w();
+                buf.core.for_each_cursor(|core| {
+                    for _ in 0..n {
+                        core.w();
+                    }
+                });
                 buf.show_cursor();
             }
             Event::Key(Key::Char('b')) => {
-                buf.core.b();
+                buf.core.for_each_cursor(|core| {
+                    for _ in 0..n {
+                        core.b();
+                    }
+                });
                 buf.show_cursor();
             }
             Event::Key(Key::Char('e')) => {
-                buf.core.e();
+                buf.core.for_each_cursor(|core| {
+                    for _ in 0..n {
+                        core.e();
+                    }
+                });
                 buf.show_cursor();
             }
             Event::Key(Key::Char('f')) => {
-                return Find { to_right: true }.into();
+                return Find {
+                    to_right: true,
+                    count: n,
+                }
+                .into();
             }
             Event::Key(Key::Char('F')) => {
-                return Find { to_right: false }.into();
+                return Find {
+                    to_right: false,
+                    count: n,
+                }
+                .into();
             }
             Event::Key(Key::Char('0')) => {
                 buf.core.set_cursor(Cursor {
@@ -330,138 +956,135 @@ impl Mode for Normal {
                     col: buf.core.current_line().len_chars(),
                 });
             }
-            Event::Key(Key::Char('g')) => {
-                buf.core.set_cursor(Cursor { row: 0, col: 0 });
-                buf.show_cursor();
+            Event::Key(Key::Char('%')) => {
+                let cursor = buf.core.cursor();
+                if let Some((_, to)) = buf.matching_bracket(cursor) {
+                    buf.core.push_jump();
+                    buf.core.set_cursor(to);
+                    buf.show_cursor();
+                }
             }
             Event::Key(Key::Char('G')) => {
-                let row = buf.core.buffer().len_lines() - 1;
-                let col = buf.core.buffer().l(row).len_chars();
-                buf.core.set_cursor(Cursor { row, col });
+                let last = buf.core.buffer().len_lines() - 1;
+                buf.core.push_jump();
+                if let Some(row) = count {
+                    // Jump to the line like `5G`
+                    let row = min(row - 1, last);
+                    buf.core.set_cursor(Cursor { row, col: 0 });
+                } else {
+                    let col = buf.core.buffer().l(last).len_chars();
+                    buf.core.set_cursor(Cursor { row: last, col });
+                }
                 buf.show_cursor();
             }
-            Event::Key(Key::Char('n')) => {
-                if !buf.search.is_empty() {
-                    let mut pos = buf.core.cursor();
-
-                    let search = buf.search.iter().collect::<String>();
-                    let ac = aho_corasick::AcAutomaton::new(vec![search]);
-
-                    if let Some(p) = buf.core.next_cursor(pos) {
-                        pos = p;
-                    } else {
-                        pos = Cursor { row: 0, col: 0 };
-                    }
-
-                    let idx = buf.core.buffer().line_to_char(pos.row) + pos.col;
-
-                    let pos_bytes = if let Some(Ok(m)) = ac
-                        .stream_find(iter_read::IterRead::new(
-                            buf.core.buffer().slice(idx..).bytes(),
-                        ))
-                        .next()
-                    {
-                        Some(buf.core.buffer().char_to_byte(idx) + m.start)
-                    } else if let Some(Ok(m)) = ac
-                        .stream_find(iter_read::IterRead::new(
-                            buf.core.buffer().slice(..idx).bytes(),
-                        ))
-                        .next()
-                    {
-                        Some(m.start)
-                    } else {
-                        None
-                    };
-
-                    if let Some(start) = pos_bytes {
-                        let row = buf.core.buffer().byte_to_line(start);
-                        let col = buf.core.buffer().byte_to_char(start)
-                            - buf.core.buffer().line_to_char(row);
-
-                        buf.core.set_cursor(Cursor { row, col });
-                        buf.show_cursor();
-                    }
+            Event::Key(Key::Char('n')) | Event::Key(Key::Char('N')) => {
+                let forward = event == Event::Key(Key::Char('n'));
+                for _ in 0..n {
+                    buf.search_next(forward);
                 }
+                buf.show_cursor();
             }
-            Event::Key(Key::Char('N')) => {
-                // TODO: Use aho-corasick. Waiting reverse iterator of ropey.
-                if !buf.search.is_empty() {
-                    let search: String = buf.search.iter().collect();
-                    let last_pos = Cursor {
-                        row: buf.core.buffer().len_lines() - 1,
-                        col: buf
-                            .core
-                            .buffer()
-                            .l(buf.core.buffer().len_lines() - 1)
-                            .len_chars(),
-                    };
-                    let orig_pos = buf.core.cursor();
-                    if !buf.core.cursor_dec() {
-                        buf.core.set_cursor(last_pos);
+            Event::Key(Key::Char('x')) => {
+                let mut deleted = String::new();
+                for i in 0..n {
+                    // Repeated `x` does not join lines
+                    if i > 0 && buf.core.char_at_cursor().is_none() {
+                        break;
                     }
-
-                    loop {
-                        let matched = buf.core.current_line_after_cursor().len_chars()
-                            >= buf.search.len()
-                            && buf
-                                .core
-                                .current_line_after_cursor()
-                                .slice(..buf.search.len())
-                                == search;
-                        if matched || buf.core.cursor() == orig_pos {
-                            buf.show_cursor();
-                            break;
-                        }
-
-                        if !buf.core.cursor_dec() {
-                            buf.core.set_cursor(last_pos);
-                        }
+                    if let Some(c) = buf.core.char_at_cursor() {
+                        deleted.push(c);
                     }
+                    buf.core.for_each_cursor(Core::delete);
                 }
-            }
-            Event::Key(Key::Char('x')) => {
-                buf.core.delete();
                 buf.core.commit();
+                if !deleted.is_empty() {
+                    let _ = buf.registers.delete(Yank {
+                        insert_newline: false,
+                        block: false,
+                        content: deleted,
+                    });
+                }
                 buf.show_cursor();
             }
-            Event::Key(Key::Char('/')) => return Search.into(),
-            Event::Key(Key::Char('v')) => {
-                return Visual {
-                    cursor: buf.core.cursor(),
-                    line_mode: false,
+            Event::Key(Key::Ctrl('a')) | Event::Key(Key::Ctrl('x')) => {
+                let delta = if event == Event::Key(Key::Ctrl('a')) {
+                    n as i128
+                } else {
+                    -(n as i128)
+                };
+                let cursor = buf.core.cursor();
+                buf.core.clear_cursors();
+                if operator::add_to_number(&mut buf.core, cursor, delta) {
+                    buf.core.commit();
+                    buf.show_cursor();
                 }
-                .into();
+            }
+            Event::Key(Key::Char('/')) => return Search::new(buf).into(),
+            Event::Key(Key::Char(':')) => {
+                // `3:` is `:.,.+2`
+                let line = count.map(|n| format!(".,.+{}", n - 1)).unwrap_or_default();
+                return CommandLine { line }.into();
+            }
+            Event::Key(Key::Char('v')) => {
+                return Visual::new(buf.core.cursor(), VisualKind::Char).into();
             }
             Event::Key(Key::Char('V')) => {
-                return Visual {
-                    cursor: buf.core.cursor(),
-                    line_mode: true,
-                }
-                .into();
+                return Visual::new(buf.core.cursor(), VisualKind::Line).into();
+            }
+            Event::Key(Key::Ctrl('v')) => {
+                return Visual::new(buf.core.cursor(), VisualKind::Block).into();
             }
             Event::Key(Key::Char('p')) => {
-                if buf.yank.insert_newline {
-                    buf.core.insert_newline();
-                } else {
-                    buf.core.cursor_right();
-                }
+                if let Some(yank) = buf.registers.get() {
+                    if yank.block {
+                        let cursor = buf.core.cursor();
+                        let col = min(cursor.col + 1, buf.core.buffer().l(cursor.row).len_chars());
+                        for _ in 0..n {
+                            put_block(&mut buf.core, &yank.content, col);
+                        }
+                        buf.core.commit();
+                        buf.show_cursor();
+                        return Transition::Nothing;
+                    }
+                    if !yank.insert_newline {
+                        buf.core.cursor_right();
+                    }
 
-                for c in buf.yank.content.chars() {
-                    buf.core.insert(c);
+                    for _ in 0..n {
+                        if yank.insert_newline {
+                            buf.core.insert_newline();
+                        }
+                        for c in yank.content.chars() {
+                            buf.core.insert(c);
+                        }
+                    }
+                    buf.core.commit();
+                    buf.show_cursor();
+                } else {
+                    self.message = "Nothing in register".into();
                 }
-                buf.core.commit();
-                buf.show_cursor();
             }
             Event::Key(Key::Char('P')) => {
-                if buf.yank.insert_newline {
-                    buf.core.insert_newline_here();
-                }
+                if let Some(yank) = buf.registers.get() {
+                    let col = buf.core.cursor().col;
+                    for _ in 0..n {
+                        if yank.block {
+                            put_block(&mut buf.core, &yank.content, col);
+                            continue;
+                        }
+                        if yank.insert_newline {
+                            buf.core.insert_newline_here();
+                        }
 
-                for c in buf.yank.content.chars() {
-                    buf.core.insert(c);
+                        for c in yank.content.chars() {
+                            buf.core.insert(c);
+                        }
+                    }
+                    buf.core.commit();
+                    buf.show_cursor();
+                } else {
+                    self.message = "Nothing in register".into();
                 }
-                buf.core.commit();
-                buf.show_cursor();
             }
             Event::Key(Key::Ctrl('p')) => {
                 if let Ok(s) = clipboard::clipboard_paste() {
@@ -477,9 +1100,6 @@ impl Mode for Normal {
             Event::Key(Key::Char(' ')) => {
                 return Prefix.into();
             }
-            Event::Key(Key::Char('z')) => {
-                buf.show_cursor_middle();
-            }
             Event::Mouse(MouseEvent::Press(MouseButton::Left, x, y)) => {
                 let col = x as usize - 1;
                 let row = y as usize - 1;
@@ -495,11 +1115,7 @@ impl Mode for Normal {
                 }
             }
             Event::Mouse(MouseEvent::Hold(_, _)) => {
-                return Visual {
-                    cursor: buf.core.cursor(),
-                    line_mode: false,
-                }
-                .into();
+                return Visual::new(buf.core.cursor(), VisualKind::Char).into();
             }
             Event::Mouse(MouseEvent::Press(MouseButton::WheelUp, _, _)) => {
                 buf.scroll_up();
@@ -507,15 +1123,13 @@ impl Mode for Normal {
             Event::Mouse(MouseEvent::Press(MouseButton::WheelDown, _, _)) => {
                 buf.scroll_down();
             }
-            Event::Key(Key::Char(c)) if c.is_digit(10) => {
-                if let Some(i) = c.to_digit(10) {
-                    return Transition::ChangeTab(i as usize);
-                }
-            }
             _ => {
                 if let Event::Key(Key::Char(c)) = event {
                     if let Some(action) = Action::from_char(c) {
-                        return Transition::RecordMacro(Box::new(TextObjectOperation::new(action)));
+                        return Transition::RecordMacro(
+                            Box::new(TextObjectOperation::new(action, n)),
+                            prefix,
+                        );
                     }
                 }
             }
@@ -535,9 +1149,36 @@ impl Mode for Normal {
         if let Some(message) = buf.compiler_message_on_cursor() {
             footer.puts(message, draw::styles::FOOTER);
         } else {
+            let label = if self.prefix == CommandPrefix::default() && !self.reading_register {
+                "[Normal]".to_string()
+            } else {
+                let mut label = String::from("[Normal ");
+                if let Some(register) = self.prefix.register {
+                    label.push('"');
+                    label.push(register);
+                } else if self.reading_register {
+                    label.push('"');
+                }
+                if let Some(count) = self.prefix.count {
+                    label.push_str(&count.to_string());
+                }
+                label.push(']');
+                label
+            };
+            let label = if let Some(register) = buf.registers.recording() {
+                format!("{} [Recording @{}]", label, register)
+            } else {
+                label
+            };
+            let label = if buf.core.cursors().is_empty() {
+                label
+            } else {
+                format!("{} [{} cursors]", label, buf.core.cursors().len() + 1)
+            };
             footer.puts(
                 &format!(
-                    "[Normal] ({} {}) [{}] {}",
+                    "{} ({} {}) [{}] {}",
+                    label,
                     buf.core.cursor().row + 1,
                     buf.core.cursor().col + 1,
                     buf.path()
@@ -679,24 +1320,25 @@ impl Mode for Insert {
                 buf.scroll_down();
             }
             Event::Key(Key::Backspace) => {
-                if buf.core.cursor() != (Cursor { col: 0, row: 0 }) {
-                    buf.core.cursor_dec();
-                    let c = buf.core.char_at_cursor();
-                    buf.core.delete();
-                    if buf.core.char_at_cursor().is_some()
-                        && buf.core.char_at_cursor()
-                            == parenthesis::PARENTHESIS_PAIRS
-                                .iter()
-                                .find(|t| c == Some(t.0))
-                                .map(|t| t.1)
-                    {
-                        buf.core.delete();
+                let pairs = buf.auto_pairs();
+                buf.core.for_each_cursor(|core| {
+                    if core.cursor() != (Cursor { col: 0, row: 0 }) {
+                        core.cursor_dec();
+                        let c = core.char_at_cursor();
+                        core.delete();
+                        // Delete the empty pair like `()` or `""`
+                        if core.char_at_cursor().is_some()
+                            && core.char_at_cursor()
+                                == pairs.iter().find(|t| c == Some(t.0)).map(|t| t.1)
+                        {
+                            core.delete();
+                        }
                     }
-                    buf.show_cursor();
-                }
+                });
+                buf.show_cursor();
             }
             Event::Key(Key::Delete) => {
-                buf.core.delete();
+                buf.core.for_each_cursor(Core::delete);
                 buf.show_cursor();
             }
             Event::Key(Key::Char('\t')) => {
@@ -707,10 +1349,18 @@ impl Mode for Insert {
                         self.completion_index = Some(0);
                     }
                 } else {
-                    buf.core.insert(' ');
-                    while buf.core.cursor().col % buf.indent_width() != 0 {
-                        buf.core.insert(' ');
-                    }
+                    let indent_width = buf.indent_width();
+                    let expand_tab = buf.expand_tab();
+                    buf.core.for_each_cursor(|core| {
+                        if !expand_tab {
+                            core.insert('\t');
+                            return;
+                        }
+                        core.insert(' ');
+                        while core.cursor().col % indent_width != 0 {
+                            core.insert(' ');
+                        }
+                    });
                 }
                 return Transition::Nothing;
             }
@@ -729,50 +1379,98 @@ impl Mode for Insert {
             Event::Key(Key::Char('\n')) => {
                 if self.completion_index.is_some() {
                     let body = &self.get_completion(buf).unwrap();
-                    Self::remove_token(&mut buf.core);
-                    for c in body.chars() {
-                        buf.core.insert(c);
-                    }
+                    buf.core.for_each_cursor(|core| {
+                        Self::remove_token(core);
+                        for c in body.chars() {
+                            core.insert(c);
+                        }
+                    });
                     buf.show_cursor();
                     self.completion_index = None;
                 } else {
-                    let indent_width = buf.indent_width();
-                    buf.core.insert('\n');
-                    let indent = indent::next_indent_level(
-                        &Cow::from(buf.core.buffer().l(buf.core.cursor().row - 1)),
-                        indent_width,
-                    );
-                    for _ in 0..indent_width * indent {
-                        buf.core.insert(' ');
-                    }
-                    let pos = buf.core.cursor();
-                    if ['}', ']', ')']
-                        .iter()
-                        .any(|&c| buf.core.char_at_cursor() == Some(c))
-                    {
-                        buf.core.insert('\n');
-                        let i = if indent == 0 { 0 } else { indent - 1 };
-                        for _ in 0..indent_width * i {
-                            buf.core.insert(' ');
+                    let (indent_width, expand_tab) = (buf.indent_width(), buf.expand_tab());
+                    let rules = buf.indent_rules().clone();
+                    buf.core.for_each_cursor(|core| {
+                        let indent_string =
+                            |width| indent::indent_string(width, indent_width, expand_tab);
+                        core.insert('\n');
+                        let indent = rules.next_indent(
+                            &Cow::from(core.buffer().l(core.cursor().row - 1)),
+                            indent_width,
+                        );
+                        let rest = String::from(core.current_line_after_cursor());
+                        if parenthesis::PARENTHESIS_RIGHTS
+                            .iter()
+                            .any(|&c| core.char_at_cursor() == Some(c))
+                        {
+                            // Open a line between brackets
+                            for c in indent_string(indent).chars() {
+                                core.insert(c);
+                            }
+                            let pos = core.cursor();
+                            core.insert('\n');
+                            for c in indent_string(indent.saturating_sub(indent_width)).chars() {
+                                core.insert(c);
+                            }
+                            core.set_cursor(pos);
+                        } else {
+                            let indent = rules.indent(
+                                &Cow::from(core.buffer().l(core.cursor().row - 1)),
+                                &rest,
+                                indent_width,
+                            );
+                            for c in indent_string(indent).chars() {
+                                core.insert(c);
+                            }
                         }
-                    }
-                    buf.core.set_cursor(pos);
+                    });
                 }
             }
             Event::Key(Key::Char(c)) if !c.is_control() => {
-                // Auto pair
-                let pairs = [('(', ')'), ('{', '}'), ('[', ']'), ('"', '"')];
-
-                if pairs.iter().any(|p| p.1 == c) && buf.core.char_at_cursor() == Some(c) {
-                    buf.core.cursor_right();
-                } else {
-                    buf.core.insert(c);
-                    let pair = pairs.iter().find(|p| p.0 == c);
-                    if let Some((_, r)) = pair {
-                        buf.core.insert(*r);
-                        buf.core.cursor_left();
+                // Auto pair. Strings and comments are judged at the main cursor.
+                let pairs = buf.auto_pairs();
+                let cursor = buf.core.cursor();
+                let in_literal = buf.in_literal(cursor);
+                let is_rust = buf.syntax_name() == "Rust";
+
+                let (indent_width, expand_tab) = (buf.indent_width(), buf.expand_tab());
+                let rules = buf.indent_rules().clone();
+                buf.core.for_each_cursor(|core| {
+                    if pairs.iter().any(|p| p.1 == c) && core.char_at_cursor() == Some(c) {
+                        core.cursor_right();
+                    } else {
+                        let was_decrease = rules.is_decrease(&Cow::from(core.current_line()));
+                        let before = String::from(core.current_line().slice(..core.cursor().col));
+                        core.insert(c);
+                        let pair = pairs
+                            .iter()
+                            .find(|p| p.0 == c)
+                            .filter(|_| !in_literal && is_pair_position(c, &before, is_rust));
+                        if let Some((_, r)) = pair {
+                            core.insert(*r);
+                            core.cursor_left();
+                        }
+                        // Dedent on typing like `}` or `else:`
+                        if !was_decrease && rules.is_decrease(&Cow::from(core.current_line())) {
+                            let row = core.cursor().row;
+                            let prev = (0..row)
+                                .rev()
+                                .map(|r| Cow::from(core.buffer().l(r)).into_owned())
+                                .find(|l| !l.trim().is_empty());
+                            if let Some(prev) = prev {
+                                let line = Cow::from(core.current_line()).into_owned();
+                                let indent = rules.indent(&prev, &line, indent_width);
+                                if indent < indent::indent_width_of(&line, indent_width) {
+                                    core.set_indent(&indent::indent_string(
+                                        indent,
+                                        indent_width,
+                                        expand_tab,
+                                    ));
+                                }
+                            }
+                        }
                     }
-                }
+                });
             }
             _ => {}
         }
@@ -831,18 +1529,97 @@ impl Mode for Insert {
 
         res
     }
-}
+}
+
+impl Mode for R {
+    fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        let core = &mut buf.core;
+        match event {
+            Event::Key(Key::Esc) => {
+                return Transition::Return(None, false);
+            }
+            Event::Key(Key::Char(c)) => {
+                let pos = core.cursor();
+                // Like Vim, do nothing if there are not enough characters to replace
+                if self.count == 1 || pos.col + self.count <= core.current_line().len_chars() {
+                    for i in 0..self.count {
+                        core.set_cursor(Cursor {
+                            row: pos.row,
+                            col: pos.col + i,
+                        });
+                        core.replace(c);
+                    }
+                }
+                return Transition::Return(None, true);
+            }
+            _ => {}
+        }
+        Transition::Nothing
+    }
+
+    fn draw(&mut self, buf: &mut Buffer, mut view: draw::TermView) -> draw::CursorState {
+        let height = view.height();
+        let width = view.width();
+        buf.draw(view.view((0, 0), height, width))
+            .map(|c| draw::CursorState::Show(c, draw::CursorShape::Underline))
+            .unwrap_or(draw::CursorState::Hide)
+    }
+}
+
+impl Mode for Search {
+    fn init(&mut self, buf: &mut Buffer) {
+        buf.search_history.reload();
+    }
 
-impl Mode for R {
     fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
-        let core = &mut buf.core;
         match event {
             Event::Key(Key::Esc) => {
+                buf.core.set_cursor(self.orig_cursor);
+                buf.set_search(self.orig_search.clone());
+                buf.show_cursor();
+                return Transition::Return(None, false);
+            }
+            Event::Key(Key::Backspace) => {
+                self.query.pop();
+                self.update(buf);
+            }
+            Event::Key(Key::Up) | Event::Key(Key::Down) => {
+                let len = buf.search_history.entries().len();
+                let index = if event == Event::Key(Key::Up) {
+                    match self.history_index {
+                        None if len > 0 => {
+                            self.draft = self.query.clone();
+                            Some(len - 1)
+                        }
+                        Some(i) => Some(i.saturating_sub(1)),
+                        None => None,
+                    }
+                } else {
+                    self.history_index.map(|i| i + 1).filter(|&i| i < len)
+                };
+                if index.is_some() || self.history_index.is_some() {
+                    self.query = index
+                        .map(|i| buf.search_history.entries()[i].clone())
+                        .unwrap_or_else(|| self.draft.clone());
+                    self.history_index = index;
+                    self.update(buf);
+                }
+            }
+            Event::Key(Key::Char('\n')) => {
+                if self.query.is_empty() {
+                    // Search the last pattern again
+                    buf.set_search(self.orig_search.clone());
+                    buf.search_next(true);
+                } else {
+                    buf.search_history.push(&self.query);
+                }
+                buf.show_cursor();
                 return Transition::Return(None, false);
             }
             Event::Key(Key::Char(c)) => {
-                core.replace(c);
-                return Transition::Return(None, true);
+                self.query.push(c);
+                self.history_index = None;
+                self.update(buf);
             }
             _ => {}
         }
@@ -850,28 +1627,45 @@ impl Mode for R {
     }
 
     fn draw(&mut self, buf: &mut Buffer, mut view: draw::TermView) -> draw::CursorState {
-        let height = view.height();
+        let height = view.height() - 1;
         let width = view.width();
-        buf.draw(view.view((0, 0), height, width))
-            .map(|c| draw::CursorState::Show(c, draw::CursorShape::Underline))
-            .unwrap_or(draw::CursorState::Hide)
+        let cursor = buf
+            .draw(view.view((0, 0), height, width))
+            .map(|c| draw::CursorState::Show(c, draw::CursorShape::Block))
+            .unwrap_or(draw::CursorState::Hide);
+
+        let mut footer = view.view((height, 0), 1, width);
+        footer.put('/', draw::styles::DEFAULT, None);
+        footer.puts(&self.query, draw::styles::DEFAULT);
+
+        cursor
     }
 }
 
-impl Mode for Search {
+impl Mode for CommandLine {
     fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
         match event {
             Event::Key(Key::Esc) => {
                 return Transition::Return(None, false);
             }
+            Event::Key(Key::Backspace) if self.line.is_empty() => {
+                return Transition::Return(None, false);
+            }
             Event::Key(Key::Backspace) => {
-                buf.search.pop();
+                self.line.pop();
+            }
+            Event::Key(Key::Char('\n')) => {
+                let message = match ex::execute(buf, &self.line) {
+                    Ok(ExResult::Quit) => return Transition::Exit,
+                    Ok(ExResult::Message(message)) => message,
+                    Err(err) => Some(err.to_string()),
+                };
+                buf.show_cursor();
+                // Commit the dot macro of `!`
+                return Transition::Return(message, true);
             }
             Event::Key(Key::Char(c)) => {
-                if c == '\n' {
-                    return Transition::Return(None, false);
-                }
-                buf.search.push(c);
+                self.line.push(c);
             }
             _ => {}
         }
@@ -887,10 +1681,8 @@ impl Mode for Search {
             .unwrap_or(draw::CursorState::Hide);
 
         let mut footer = view.view((height, 0), 1, width);
-        footer.put('/', draw::styles::DEFAULT, None);
-        for &c in &buf.search {
-            footer.put(c, draw::styles::DEFAULT, None);
-        }
+        footer.put(':', draw::styles::DEFAULT, None);
+        footer.puts(&self.line, draw::styles::DEFAULT);
 
         cursor
     }
@@ -1026,10 +1818,9 @@ impl Mode for Prefix {
                     buf.format();
                     buf.save(is_optimize);
                     buf.wait_compile_message();
-                    if let Some(stem) = path.file_stem() {
-                        let mut prog = OsString::from("./");
-                        prog.push(stem);
-                        if let Ok(mut child) = process::Command::new(&prog)
+                    if let Some(prog) = crate::test_case::program(&path) {
+                        let limits = buf.limits();
+                        if let Ok(mut child) = crate::test_case::command(&prog, &limits)
                             .stdout(process::Stdio::piped())
                             .stderr(process::Stdio::piped())
                             .stdin(process::Stdio::piped())
@@ -1039,7 +1830,7 @@ impl Mode for Prefix {
                                 if let Some(mut stdin) = child.stdin.take() {
                                     let _ = write!(stdin, "{}", input);
                                 }
-                                if let Some(next_state) = ViewProcess::with_process(child) {
+                                if let Some(next_state) = ViewProcess::with_process(child, limits) {
                                     return next_state.into();
                                 } else {
                                     return Normal::with_message("Failed to test".into()).into();
@@ -1058,6 +1849,29 @@ impl Mode for Prefix {
                     return Normal::with_message("Save first".into()).into();
                 }
             }
+            Event::Key(Key::Char('j')) => {
+                if let Some(next_state) = test_case::TestCases::new(buf) {
+                    return next_state.into();
+                } else {
+                    return Normal::with_message("Save first".into()).into();
+                }
+            }
+            Event::Key(Key::Char('i')) => {
+                if let Some(mut next_state) = interactive::Interactive::new(buf) {
+                    next_state.start(buf);
+                    return next_state.into();
+                } else {
+                    return Normal::with_message("Save first".into()).into();
+                }
+            }
+            Event::Key(Key::Char('x')) => {
+                if let Some(mut next_state) = stress::Stress::new(buf) {
+                    next_state.start(buf);
+                    return next_state.into();
+                } else {
+                    return Normal::with_message("Save first".into()).into();
+                }
+            }
             Event::Key(Key::Char('c')) => {
                 return Transition::CreateNewTab;
             }
@@ -1096,8 +1910,135 @@ impl Mode for Prefix {
 }
 
 impl Visual {
+    fn new(cursor: Cursor, kind: VisualKind) -> Self {
+        Self {
+            cursor,
+            kind,
+            reading_register: false,
+            reading_g: false,
+        }
+    }
+
+    // Columns of the block. Both ends are inclusive.
+    fn block_cols(&self, to: Cursor) -> (usize, usize) {
+        (min(self.cursor.col, to.col), max(self.cursor.col, to.col))
+    }
+
+    // The selected part of each line. Lines shorter than the block are skipped.
+    fn block_ranges(&self, to: Cursor, buf: &Rope) -> Vec<CursorRange> {
+        let (l, r) = self.block_cols(to);
+        (min(self.cursor.row, to.row)..=max(self.cursor.row, to.row))
+            .filter_map(|row| {
+                let len = buf.l(row).len_chars();
+                if l < len {
+                    Some(CursorRange(
+                        Cursor { row, col: l },
+                        Cursor {
+                            row,
+                            col: min(r, len - 1),
+                        },
+                    ))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn selected(&self, to: Cursor, buf: &Rope) -> Vec<CursorRange> {
+        if self.kind == VisualKind::Block {
+            self.block_ranges(to, buf)
+        } else {
+            vec![self.get_range(to, buf)]
+        }
+    }
+
+    // Lines of the block joined by newlines. Short lines give empty lines.
+    fn block_string(&self, to: Cursor, core: &Core) -> String {
+        let ranges = self.block_ranges(to, core.buffer());
+        (min(self.cursor.row, to.row)..=max(self.cursor.row, to.row))
+            .map(|row| {
+                ranges
+                    .iter()
+                    .find(|range| range.0.row == row)
+                    .map(|&range| String::from(core.get_slice_by_range(range)))
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // A cursor on each selected line at the column given by the line length. Short lines are
+    // padded with spaces to reach the column. Lines given None are skipped like Vim.
+    fn set_block_cursors<F: Fn(usize) -> Option<usize>>(
+        &self,
+        buf: &mut Buffer,
+        to: Cursor,
+        col: F,
+    ) {
+        let top = min(self.cursor.row, to.row);
+        let bottom = max(self.cursor.row, to.row);
+        buf.core.clear_cursors();
+        let mut cursors = Vec::new();
+        for row in top..=bottom {
+            let len = buf.core.buffer().l(row).len_chars();
+            if let Some(col) = col(len) {
+                buf.core.set_cursor(Cursor { row, col: len });
+                for _ in len..col {
+                    buf.core.insert(' ');
+                }
+                cursors.push(Cursor { row, col });
+            }
+        }
+        if let Some((&first, rest)) = cursors.split_first() {
+            // The bottom cursor becomes the main one
+            buf.core.set_cursor(first);
+            for &cursor in rest {
+                buf.core.add_cursor(cursor);
+            }
+        } else {
+            let col = min(self.block_cols(to).0, buf.core.buffer().l(top).len_chars());
+            buf.core.set_cursor(Cursor { row: top, col });
+        }
+    }
+
+    // Ctrl-a and Ctrl-x on each line. `sequence` adds `delta` more for each line like g Ctrl-a.
+    fn add_to_numbers(&self, buf: &mut Buffer, delta: i128, sequence: bool) -> Transition {
+        let to = buf.core.cursor();
+        let range = self.get_range(to, buf.core.buffer());
+        let (block_l, _) = self.block_cols(to);
+        buf.core.clear_cursors();
+        let mut k = 1;
+        for row in range.l().row..=range.r().row {
+            let col = match self.kind {
+                VisualKind::Block => block_l,
+                VisualKind::Char if row == range.l().row => range.l().col,
+                _ => 0,
+            };
+            let delta = if sequence { delta * k } else { delta };
+            if operator::add_to_number(&mut buf.core, Cursor { row, col }, delta) {
+                k += 1;
+            }
+        }
+        buf.core.commit();
+        buf.core.set_cursor(range.l());
+        buf.show_cursor();
+        Transition::Return(None, false)
+    }
+
     fn get_range(&self, to: Cursor, buf: &Rope) -> CursorRange {
-        if self.line_mode {
+        if self.kind == VisualKind::Block {
+            let (l, r) = self.block_cols(to);
+            let row_l = min(self.cursor.row, to.row);
+            let row_r = max(self.cursor.row, to.row);
+            CursorRange(
+                Cursor { row: row_l, col: l },
+                Cursor {
+                    row: row_r,
+                    col: min(r, buf.l(row_r).len_chars()),
+                },
+            )
+        } else if self.kind == VisualKind::Line {
             let mut l = min(self.cursor, to);
             let mut r = max(self.cursor, to);
 
@@ -1113,10 +2054,74 @@ impl Visual {
 
 impl Mode for Visual {
     fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        // The selection before the key which ends Visual mode
+        buf.last_visual = Some(CursorRange(self.cursor, buf.core.cursor()));
+        if self.reading_register {
+            self.reading_register = false;
+            if let Event::Key(Key::Char(c)) = event {
+                if Registers::is_valid(c) {
+                    buf.registers.select(Some(c));
+                }
+            }
+            return Transition::Nothing;
+        }
+        if std::mem::replace(&mut self.reading_g, false) {
+            match event {
+                Event::Key(Key::Char('g')) => {
+                    buf.core.set_cursor(Cursor { row: 0, col: 0 });
+                    buf.show_cursor();
+                }
+                // gc
+                Event::Key(Key::Char('c')) => {
+                    let range = self.get_range(buf.core.cursor(), buf.core.buffer());
+                    if let Some(comment) = buf.comment() {
+                        buf.core.clear_cursors();
+                        operator::toggle_comment(buf, range.l().row..=range.r().row, comment);
+                        buf.core.commit();
+                        buf.show_cursor();
+                        return Transition::Return(None, true);
+                    }
+                    return Transition::Return(Some("No comment for this language".into()), false);
+                }
+                // g Ctrl-a and g Ctrl-x
+                Event::Key(Key::Ctrl('a')) | Event::Key(Key::Ctrl('x')) => {
+                    let delta = if event == Event::Key(Key::Ctrl('a')) {
+                        1
+                    } else {
+                        -1
+                    };
+                    return self.add_to_numbers(buf, delta, true);
+                }
+                _ => {}
+            }
+            return Transition::Nothing;
+        }
         match event {
+            Event::Key(Key::Char('"')) => {
+                self.reading_register = true;
+            }
             Event::Key(Key::Esc) => {
                 return Transition::Return(None, false);
             }
+            Event::Key(Key::Char('v'))
+            | Event::Key(Key::Char('V'))
+            | Event::Key(Key::Ctrl('v')) => {
+                let kind = match event {
+                    Event::Key(Key::Char('v')) => VisualKind::Char,
+                    Event::Key(Key::Char('V')) => VisualKind::Line,
+                    _ => VisualKind::Block,
+                };
+                if kind == self.kind {
+                    return Transition::Return(None, false);
+                }
+                self.kind = kind;
+            }
+            Event::Key(Key::Char(':')) => {
+                return CommandLine {
+                    line: "'<,'>".into(),
+                }
+                .into();
+            }
             Event::Key(Key::Char('h')) => {
                 buf.core.cursor_left();
                 buf.show_cursor();
@@ -1146,8 +2151,16 @@ impl Mode for Visual {
                 buf.show_cursor();
             }
             Event::Key(Key::Char('g')) => {
-                buf.core.set_cursor(Cursor { row: 0, col: 0 });
-                buf.show_cursor();
+                self.reading_g = true;
+            }
+            Event::Key(Key::Ctrl('a')) => return self.add_to_numbers(buf, 1, false),
+            Event::Key(Key::Ctrl('x')) => return self.add_to_numbers(buf, -1, false),
+            Event::Key(Key::Char('%')) => {
+                let cursor = buf.core.cursor();
+                if let Some((_, to)) = buf.matching_bracket(cursor) {
+                    buf.core.set_cursor(to);
+                    buf.show_cursor();
+                }
             }
             Event::Key(Key::Char('G')) => {
                 let row = buf.core.buffer().len_lines() - 1;
@@ -1159,9 +2172,38 @@ impl Mode for Visual {
             | Event::Key(Key::Char('x'))
             | Event::Key(Key::Char('s')) => {
                 let to_insert = event == Event::Key(Key::Char('s'));
+                if self.kind == VisualKind::Block {
+                    let to = buf.core.cursor();
+                    let s = self.block_string(to, &buf.core);
+                    for range in self.block_ranges(to, buf.core.buffer()).into_iter().rev() {
+                        buf.core.delete_range(range);
+                    }
+                    buf.core.commit();
+                    let _ = buf.registers.delete(Yank {
+                        insert_newline: false,
+                        block: true,
+                        content: s,
+                    });
+                    let (l, _) = self.block_cols(to);
+                    if to_insert {
+                        self.set_block_cursors(
+                            buf,
+                            to,
+                            |len| if l <= len { Some(l) } else { None },
+                        );
+                    } else {
+                        self.set_block_cursors(buf, to, |_| None);
+                    }
+                    buf.show_cursor();
+                    return if to_insert {
+                        Insert::default().into()
+                    } else {
+                        Transition::Return(Some("Deleted".into()), true)
+                    };
+                }
                 let range = self.get_range(buf.core.cursor(), buf.core.buffer());
-                let s = if self.line_mode {
-                    String::from(buf.core.get_slice_by_range(range).trim_end())
+                let s = if self.kind == VisualKind::Line {
+                    String::from(buf.core.get_slice_by_range(range).trim_last_line_end())
                 } else {
                     String::from(buf.core.get_slice_by_range(range))
                 };
@@ -1174,8 +2216,11 @@ impl Mode for Visual {
                     buf.indent();
                 }
                 buf.core.commit();
-                buf.yank.insert_newline = self.line_mode;
-                buf.yank.content = s;
+                let _ = buf.registers.delete(Yank {
+                    insert_newline: self.kind == VisualKind::Line,
+                    block: false,
+                    content: s,
+                });
 
                 buf.show_cursor();
                 return if to_insert {
@@ -1187,16 +2232,34 @@ impl Mode for Visual {
             Event::Key(Key::Char('p')) | Event::Key(Key::Ctrl('p')) => {
                 let is_clipboard = event == Event::Key(Key::Ctrl('p'));
                 let range = self.get_range(buf.core.cursor(), buf.core.buffer());
-                buf.core.delete_range(range);
+                if self.kind == VisualKind::Block {
+                    let to = buf.core.cursor();
+                    for range in self.block_ranges(to, buf.core.buffer()).into_iter().rev() {
+                        buf.core.delete_range(range);
+                    }
+                    let (l, _) = self.block_cols(to);
+                    let col = min(l, buf.core.buffer().l(range.l().row).len_chars());
+                    buf.core.set_cursor(Cursor {
+                        row: range.l().row,
+                        col,
+                    });
+                } else {
+                    buf.core.delete_range(range);
+                }
                 if is_clipboard {
                     if let Ok(s) = clipboard::clipboard_paste() {
                         for c in s.chars() {
                             buf.core.insert(c);
                         }
                     }
-                } else {
-                    for c in buf.yank.content.chars() {
-                        buf.core.insert(c);
+                } else if let Some(yank) = buf.registers.get() {
+                    if yank.block {
+                        let col = buf.core.cursor().col;
+                        put_block(&mut buf.core, &yank.content, col);
+                    } else {
+                        for c in yank.content.chars() {
+                            buf.core.insert(c);
+                        }
                     }
                 }
                 buf.core.commit();
@@ -1206,8 +2269,10 @@ impl Mode for Visual {
             Event::Key(Key::Char('y')) | Event::Key(Key::Ctrl('y')) => {
                 let is_clipboard = event == Event::Key(Key::Ctrl('y'));
                 let range = self.get_range(buf.core.cursor(), buf.core.buffer());
-                let s = if self.line_mode {
-                    String::from(buf.core.get_slice_by_range(range).trim_end())
+                let s = if self.kind == VisualKind::Block {
+                    self.block_string(buf.core.cursor(), &buf.core)
+                } else if self.kind == VisualKind::Line {
+                    String::from(buf.core.get_slice_by_range(range).trim_last_line_end())
                 } else {
                     String::from(buf.core.get_slice_by_range(range))
                 };
@@ -1218,12 +2283,36 @@ impl Mode for Visual {
                     } else {
                         return Transition::Return(Some("Yank failed".into()), false);
                     }
-                } else {
-                    buf.yank.insert_newline = self.line_mode;
-                    buf.yank.content = s;
+                } else if buf
+                    .registers
+                    .yank(Yank {
+                        insert_newline: self.kind == VisualKind::Line,
+                        block: self.kind == VisualKind::Block,
+                        content: s,
+                    })
+                    .is_err()
+                {
+                    return Transition::Return(Some("Yank failed".into()), false);
                 }
                 return Transition::Return(Some("Yanked".into()), false);
             }
+            Event::Key(Key::Char('I')) | Event::Key(Key::Char('A')) => {
+                // A cursor on each line at the left column or the line end
+                let is_append = event == Event::Key(Key::Char('A'));
+                let to = buf.core.cursor();
+                let (l, r) = self.block_cols(to);
+                let kind = self.kind;
+                self.set_block_cursors(buf, to, |len| match (kind, is_append) {
+                    (VisualKind::Block, true) => Some(r + 1),
+                    (_, true) => Some(len),
+                    (VisualKind::Block, false) if len < l => None,
+                    (VisualKind::Block, false) => Some(l),
+                    (VisualKind::Line, false) => Some(0),
+                    (VisualKind::Char, false) => Some(min(l, len)),
+                });
+                buf.show_cursor();
+                return Insert::default().into();
+            }
             Event::Key(Key::Char('S')) => {
                 let range = self.get_range(buf.core.cursor(), buf.core.buffer());
                 return S(range).into();
@@ -1265,8 +2354,8 @@ impl Mode for Visual {
     fn draw(&mut self, buf: &mut Buffer, mut view: draw::TermView) -> draw::CursorState {
         let height = view.height();
         let width = view.width();
-        let range = self.get_range(buf.core.cursor(), buf.core.buffer());
-        buf.draw_with_selected(view.view((0, 0), height, width), Some(range))
+        let selected = self.selected(buf.core.cursor(), buf.core.buffer());
+        buf.draw_with_selected(view.view((0, 0), height, width), &selected)
             .map(|c| draw::CursorState::Show(c, draw::CursorShape::Block))
             .unwrap_or(draw::CursorState::Hide)
     }
@@ -1293,19 +2382,32 @@ impl Mode for ViewProcess {
     }
 
     fn draw(&mut self, _buf: &mut Buffer, mut view: draw::TermView) -> draw::CursorState {
-        if self.end.is_none() {
-            if let Ok(Some(_)) = self.process.try_wait() {
-                self.end = Some(Instant::now());
-            }
-        }
         let mut read_cnt = 32;
-        while let Ok(line) = self.reader.try_recv() {
+        while let Ok((line, is_stderr)) = self.reader.try_recv() {
             if read_cnt == 0 {
                 break;
             }
+            if is_stderr {
+                self.stderr.push_str(&line);
+                self.stderr.push('\n');
+            }
             self.buf.push(line);
             read_cnt -= 1;
         }
+        if self.end.is_none() {
+            if let Some(usage) = self.waiter.try_recv() {
+                self.end = Some(Instant::now());
+                if let Ok(usage) = usage {
+                    self.memory = usage.memory;
+                    self.verdict = self.verdict.or_else(|| {
+                        crate::test_case::limit_verdict(&usage, &self.limits, &self.stderr)
+                    });
+                }
+            } else if self.verdict.is_none() && self.start.elapsed() > self.limits.time {
+                self.waiter.kill();
+                self.verdict = Some(Verdict::TLE);
+            }
+        }
 
         let height = view.height();
         let width = view.width();
@@ -1317,6 +2419,15 @@ impl Mode for ViewProcess {
             }
             if let Some(end) = self.end {
                 view.puts(&format!("{:?}", end - self.start), draw::styles::HIGHLIGHT);
+                if let Some(memory) = self.memory {
+                    view.puts(
+                        &format!(" {:.1} MB", memory as f64 / (1 << 20) as f64),
+                        draw::styles::HIGHLIGHT,
+                    );
+                }
+                if let Some(verdict) = self.verdict {
+                    view.puts(&format!(" {}", verdict.as_str()), draw::styles::HIGHLIGHT);
+                }
             }
         }
         {
@@ -1333,25 +2444,44 @@ impl Mode for TextObjectOperation {
             return Transition::Return(None, false);
         }
         if let Event::Key(Key::Char(c)) = event {
+            if let Some(d) = c.to_digit(10) {
+                // Digits after `f` or `t` are characters to find
+                if (d != 0 || self.inner_count.is_some())
+                    && self.parser.prefix
+                        == text_object::Prefix::TextObjectPrefix(
+                            text_object::TextObjectPrefix::None,
+                        )
+                {
+                    self.inner_count = Some(push_count_digit(self.inner_count, d));
+                    return Transition::Nothing;
+                }
+            }
+
+            let count = self.count();
+
             if c == self.parser.action.to_char() {
-                // Yank current line
-                buf.yank = Yank {
+                let row = buf.core.cursor().row;
+                let last = min(row + count - 1, buf.core.buffer().len_lines() - 1);
+                let range = CursorRange(
+                    Cursor { row, col: 0 },
+                    Cursor {
+                        row: last,
+                        col: buf.core.buffer().l(last).len_chars(),
+                    },
+                );
+                if let Some(t) = self.operate(buf, range) {
+                    return t;
+                }
+                // Yank current lines
+                let yank = Yank {
                     insert_newline: true,
-                    content: String::from(buf.core.current_line()),
+                    block: false,
+                    content: String::from(buf.core.get_slice_by_range(range).trim_last_line_end()),
                 };
+                self.store(buf, yank);
                 match self.parser.action {
                     // dd
                     Action::Delete => {
-                        let range = CursorRange(
-                            Cursor {
-                                row: buf.core.cursor().row,
-                                col: 0,
-                            },
-                            Cursor {
-                                row: buf.core.cursor().row,
-                                col: buf.core.current_line().len_chars(),
-                            },
-                        );
                         buf.core.delete_range(range);
                         buf.core.commit();
                         return Transition::Return(None, true);
@@ -1360,57 +2490,56 @@ impl Mode for TextObjectOperation {
                         return Transition::Return(None, false);
                     }
                     Action::Change => {
-                        let pos = buf.core.cursor();
-                        buf.core.set_cursor(Cursor {
-                            row: pos.row,
-                            col: 0,
-                        });
-                        for _ in 0..buf.core.current_line().len_chars() {
-                            buf.core.delete();
+                        if let Some(range) = lines_range(&buf.core, row, last) {
+                            buf.core.delete_range(range);
                         }
                         buf.core.commit();
                         buf.indent();
                         return Insert::default().into();
                     }
+                    _ => unreachable!(),
                 }
             }
 
             if c == 'j' || c == 'k' {
+                let row = buf.core.cursor().row;
                 let range = if c == 'j' {
-                    if buf.core.cursor().row == buf.core.buffer().len_lines() - 1 {
+                    if row == buf.core.buffer().len_lines() - 1 {
                         return Transition::Return(None, false);
                     }
-                    let next_line = buf.core.buffer().l(buf.core.cursor().row + 1).len_chars();
+                    let last = min(row + count, buf.core.buffer().len_lines() - 1);
                     CursorRange(
+                        Cursor { row, col: 0 },
                         Cursor {
-                            row: buf.core.cursor().row,
-                            col: 0,
-                        },
-                        Cursor {
-                            row: buf.core.cursor().row + 1,
-                            col: next_line,
+                            row: last,
+                            col: buf.core.buffer().l(last).len_chars(),
                         },
                     )
                 } else {
-                    if buf.core.cursor().row == 0 {
+                    if row == 0 {
                         return Transition::Return(None, false);
                     }
                     CursorRange(
                         Cursor {
-                            row: buf.core.cursor().row - 1,
+                            row: row.saturating_sub(count),
                             col: 0,
                         },
                         Cursor {
-                            row: buf.core.cursor().row,
+                            row,
                             col: buf.core.current_line().len_chars(),
                         },
                     )
                 };
 
-                buf.yank = Yank {
+                if let Some(t) = self.operate(buf, range) {
+                    return t;
+                }
+                let yank = Yank {
                     insert_newline: true,
-                    content: String::from(buf.core.get_slice_by_range(range).trim_end()),
+                    block: false,
+                    content: String::from(buf.core.get_slice_by_range(range).trim_last_line_end()),
                 };
+                self.store(buf, yank);
                 match self.parser.action {
                     // dj or dk
                     Action::Delete => {
@@ -1428,30 +2557,58 @@ impl Mode for TextObjectOperation {
                         buf.indent();
                         return Insert::default().into();
                     }
+                    _ => unreachable!(),
                 }
             }
 
+            if c == '%'
+                && self.parser.prefix
+                    == text_object::Prefix::TextObjectPrefix(text_object::TextObjectPrefix::None)
+            {
+                let cursor = buf.core.cursor();
+                return match buf.matching_bracket(cursor) {
+                    // From the cursor to the pair like `d%`
+                    Some((_, to)) if to > cursor => {
+                        self.operate_chars(buf, CursorRange(cursor, to))
+                    }
+                    Some((from, to)) => self.operate_chars(buf, CursorRange(to, from)),
+                    None => Transition::Return(None, false),
+                };
+            }
+
+            if c == 's'
+                && self.parser.prefix
+                    == text_object::Prefix::TextObjectPrefix(text_object::TextObjectPrefix::None)
+                && [Action::Delete, Action::Change, Action::Yank].contains(&self.parser.action)
+            {
+                return Surround::new(self.parser.action, count).into();
+            }
+
+            self.parser.count = count;
             if let Some(half_range) = self.parser.parse(c, &buf.core) {
-                if let Some(range) = half_range {
-                    buf.yank = Yank {
-                        insert_newline: false,
-                        content: String::from(buf.core.get_slice_by_range(range)),
-                    };
-                    match self.parser.action {
-                        Action::Delete => {
-                            buf.core.delete_range(range);
-                            buf.core.commit();
-                            return Transition::Return(None, true);
-                        }
-                        Action::Change => {
-                            buf.core.delete_range(range);
-                            buf.core.commit();
-                            return Insert::default().into();
-                        }
-                        Action::Yank => {
-                            return Transition::Return(None, false);
+                if let Some(mut range) = half_range {
+                    if self.parser.prefix
+                        == text_object::Prefix::TextObjectPrefix(
+                            text_object::TextObjectPrefix::None,
+                        )
+                    {
+                        // Extend motions like `3dw` by parsing again from the end of the range
+                        let pos = buf.core.cursor();
+                        for _ in 1..count {
+                            if let Some(next) = buf.core.next_cursor(range.r()) {
+                                buf.core.set_cursor(next);
+                            } else {
+                                break;
+                            }
+                            if let Some(Some(next_range)) = self.parser.parse(c, &buf.core) {
+                                range = CursorRange(range.l(), next_range.r());
+                            } else {
+                                break;
+                            }
                         }
+                        buf.core.set_cursor(pos);
                     }
+                    return self.operate_chars(buf, range);
                 } else {
                     return Transition::Return(None, true);
                 }
@@ -1470,16 +2627,12 @@ impl Mode for TextObjectOperation {
 
         let mut footer = view.view((height, 0), 1, width);
 
-        match self.parser.action {
-            Action::Change => {
-                footer.puts("Change ", draw::styles::FOOTER);
-            }
-            Action::Delete => {
-                footer.puts("Delete ", draw::styles::FOOTER);
-            }
-            Action::Yank => {
-                footer.puts("Yank ", draw::styles::FOOTER);
-            }
+        footer.puts(
+            &format!("{} ", self.parser.action.name()),
+            draw::styles::FOOTER,
+        );
+        if self.count > 1 || self.inner_count.is_some() {
+            footer.puts(&format!("{} ", self.count()), draw::styles::FOOTER);
         }
 
         cursor
@@ -1493,20 +2646,7 @@ impl Mode for S {
                 return Transition::Return(None, false);
             }
             Event::Key(Key::Char(c)) if !c.is_control() => {
-                let l = self.0.l();
-                let r = self.0.r();
-
-                let (cl, cr) = parenthesis::PARENTHESIS_PAIRS
-                    .iter()
-                    .map(|(l, r)| (*l, *r))
-                    .find(|&(l, r)| c == l || c == r)
-                    .unwrap_or((c, c));
-
-                buf.core.set_cursor(r);
-                buf.core.cursor_inc();
-                buf.core.insert(cr);
-                buf.core.set_cursor(l);
-                buf.core.insert(cl);
+                operator::add_surround(&mut buf.core, self.0, c);
                 buf.core.commit();
 
                 return Transition::Return(None, false);
@@ -1520,12 +2660,105 @@ impl Mode for S {
         let height = view.height();
         let width = view.width();
         let range = self.0;
-        buf.draw_with_selected(view.view((0, 0), height, width), Some(range))
+        buf.draw_with_selected(view.view((0, 0), height, width), &[range])
             .map(|c| draw::CursorState::Show(c, draw::CursorShape::Block))
             .unwrap_or(draw::CursorState::Hide)
     }
 }
 
+impl Surround {
+    fn new(action: Action, count: usize) -> Self {
+        // Motions like `cw` don't take trailing spaces
+        let mut parser = text_object::TextObjectParser::new(Action::Change);
+        parser.count = count;
+        Self {
+            action,
+            target: None,
+            parser,
+            range: None,
+        }
+    }
+}
+
+impl Mode for Surround {
+    fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        let c = match event {
+            Event::Key(Key::Esc) => return Transition::Return(None, false),
+            Event::Key(Key::Char(c)) if !c.is_control() => c,
+            _ => return Transition::Nothing,
+        };
+        match self.action {
+            Action::Yank if self.range.is_none() => {
+                if c == 's'
+                    && self.parser.prefix
+                        == text_object::Prefix::TextObjectPrefix(
+                            text_object::TextObjectPrefix::None,
+                        )
+                {
+                    // yss surrounds the line without the indent
+                    let row = buf.core.cursor().row;
+                    let line = String::from(buf.core.current_line());
+                    let l = indent::indent_len(&line);
+                    let r = line.trim_end().chars().count();
+                    if l == r {
+                        return Transition::Return(None, false);
+                    }
+                    self.range = Some(CursorRange(
+                        Cursor { row, col: l },
+                        Cursor { row, col: r - 1 },
+                    ));
+                } else {
+                    match self.parser.parse(c, &buf.core) {
+                        Some(Some(range)) => self.range = Some(range),
+                        Some(None) => return Transition::Return(None, false),
+                        None => {}
+                    }
+                }
+                Transition::Nothing
+            }
+            Action::Change if self.target.is_none() => {
+                self.target = Some(c);
+                Transition::Nothing
+            }
+            _ => {
+                buf.core.clear_cursors();
+                let done = if let Some(range) = self.range {
+                    operator::add_surround(&mut buf.core, range, c);
+                    true
+                } else if let Some(target) = self.target {
+                    operator::change_surround(&mut buf.core, target, Some(c))
+                } else {
+                    operator::change_surround(&mut buf.core, c, None)
+                };
+                if done {
+                    buf.core.commit();
+                    buf.show_cursor();
+                }
+                Transition::Return(None, done)
+            }
+        }
+    }
+
+    fn draw(&mut self, buf: &mut Buffer, mut view: draw::TermView) -> draw::CursorState {
+        let height = view.height() - 1;
+        let width = view.width();
+        let cursor = buf
+            .draw(view.view((0, 0), height, width))
+            .map(|c| draw::CursorState::Show(c, draw::CursorShape::Block))
+            .unwrap_or(draw::CursorState::Hide);
+
+        let mut footer = view.view((height, 0), 1, width);
+        let name = match self.action {
+            Action::Delete => "Delete surround",
+            Action::Change => "Change surround",
+            _ => "Surround",
+        };
+        footer.puts(name, draw::styles::FOOTER);
+
+        cursor
+    }
+}
+
 impl Mode for Find {
     fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
         match event {
@@ -1540,13 +2773,17 @@ impl Mode for Find {
                     Box::new((0..cursor.col).rev())
                 };
 
+                let mut count = self.count;
                 for i in range {
                     if buf.core.current_line().char(i) == c {
-                        buf.core.set_cursor(Cursor {
-                            row: cursor.row,
-                            col: i,
-                        });
-                        break;
+                        count -= 1;
+                        if count == 0 {
+                            buf.core.set_cursor(Cursor {
+                                row: cursor.row,
+                                col: i,
+                            });
+                            break;
+                        }
                     }
                 }
                 return Transition::Return(None, false);
@@ -1591,6 +2828,7 @@ impl Mode for Goto {
                         }
                         row = min(row, buf.core.buffer().len_lines() - 1);
 
+                        buf.core.push_jump();
                         buf.core.set_cursor(Cursor { row, col: 0 });
                         buf.show_cursor();
                         return Transition::Return(None, false);
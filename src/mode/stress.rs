@@ -0,0 +1,174 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use termion::event::{Event, Key};
+
+use super::{Mode, Transition};
+use crate::buffer::Buffer;
+use crate::config::types::keys;
+use crate::draw;
+use crate::stress::{self, Programs, Progress};
+use crate::test_case::{self, Compare};
+
+// Stress test of the buffer by a generator and a brute force
+pub struct Stress {
+    path: PathBuf,
+    passed: usize,
+    running: Option<mpsc::Receiver<Progress>>,
+    stop: Arc<AtomicBool>,
+    message: String,
+    // The failing input to open
+    found: Option<PathBuf>,
+}
+
+impl Drop for Stress {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Stress {
+    pub fn new(buf: &Buffer) -> Option<Self> {
+        Some(Self {
+            path: buf.path()?.to_path_buf(),
+            passed: 0,
+            running: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            message: String::new(),
+            found: None,
+        })
+    }
+
+    pub fn start(&mut self, buf: &mut Buffer) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.running = None;
+        self.passed = 0;
+        self.found = None;
+
+        buf.format();
+        buf.save(true);
+        buf.wait_compile_message();
+        if buf.last_compile_success() == Some(false) {
+            self.message = "Compile error".into();
+            return;
+        }
+        let path = self.path.clone();
+        let dir = if let Some(dir) = test_case::test_dir(&path) {
+            dir
+        } else {
+            return;
+        };
+
+        self.message = "Running...".into();
+        let config = buf.get_config::<keys::Compiler>().cloned();
+        let limits = buf.limits();
+        let stop = Arc::new(AtomicBool::new(false));
+        self.stop = stop.clone();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            // Build the generator and the brute force here not to block the UI
+            let programs = (|| -> Result<Programs, String> {
+                let config = config.as_ref();
+                Ok(Programs {
+                    solution: test_case::program(&path).ok_or("Failed to run")?,
+                    generator: stress::build(
+                        config,
+                        &stress::generator(&path).ok_or("Failed to run")?,
+                    )?,
+                    brute: stress::build(config, &stress::brute(&path).ok_or("Failed to run")?)?,
+                })
+            })();
+            match programs {
+                Ok(programs) => {
+                    stress::run(&programs, &dir, &limits, Compare::default(), &stop, &tx)
+                }
+                Err(e) => {
+                    let _ = tx.send(Progress::Error(e));
+                }
+            }
+        });
+        self.running = Some(rx);
+    }
+}
+
+impl Mode for Stress {
+    fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        match event {
+            Event::Key(Key::Esc) => return Transition::Return(None, false),
+            Event::Key(Key::Char('r')) => self.start(buf),
+            Event::Key(Key::Char('i')) => {
+                if let Some(path) = self.found.clone() {
+                    return Transition::OpenNewTab(path);
+                }
+            }
+            _ => {}
+        }
+        Transition::Nothing
+    }
+
+    fn poll(&mut self, _buf: &mut Buffer) -> Transition {
+        let mut found = None;
+        if let Some(running) = self.running.as_ref() {
+            while let Ok(progress) = running.try_recv() {
+                match progress {
+                    Progress::Passed(n) => self.passed = n,
+                    Progress::Found(n, verdict, case) => {
+                        self.message = format!(
+                            "{} on iteration {}. Saved as case {}",
+                            verdict.as_str(),
+                            n,
+                            case.name
+                        );
+                        found = Some(case.input);
+                    }
+                    Progress::Error(e) => self.message = e,
+                }
+            }
+        }
+        if let Some(path) = found {
+            self.running = None;
+            self.found = Some(path.clone());
+            return Transition::OpenNewTab(path);
+        }
+        Transition::Nothing
+    }
+
+    fn draw(&mut self, _buf: &mut Buffer, mut view: draw::TermView) -> draw::CursorState {
+        let height = view.height();
+        let width = view.width();
+        {
+            let mut view = view.view((0, 0), height - 1, width);
+            let names = [
+                ("Solution", Some(self.path.clone())),
+                ("Generator", stress::generator(&self.path)),
+                ("Brute force", stress::brute(&self.path)),
+            ];
+            for (name, path) in names.iter() {
+                view.puts(&format!("{:<12}", name), draw::styles::UI);
+                if let Some(path) = path {
+                    view.puts(&path.display().to_string(), draw::styles::DEFAULT);
+                }
+                view.newline();
+            }
+            view.newline();
+            let style = if self.found.is_some() {
+                draw::styles::HIGHLIGHT
+            } else {
+                draw::styles::ACCEPTED
+            };
+            view.puts(&format!("{} passed", self.passed), style);
+            view.newline();
+            view.puts(&self.message, style);
+        }
+        {
+            let mut footer = view.view((height - 1, 0), 1, width);
+            footer.puts(
+                "[r: Restart] [i: Failing input] [Esc: Stop and return]",
+                draw::styles::FOOTER,
+            );
+        }
+        draw::CursorState::Hide
+    }
+}
@@ -0,0 +1,361 @@
+use std::cmp::{max, min};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+
+use termion::event::{Event, Key};
+
+use super::{Mode, Transition};
+use crate::buffer::Buffer;
+use crate::draw;
+use crate::test_case::{self, Compare, TestCase, TestResult, Verdict};
+
+// Test cases of the buffer. Runs all of them and shows the verdicts.
+pub struct TestCases {
+    dir: PathBuf,
+    cases: Vec<TestCase>,
+    results: Vec<Option<TestResult>>,
+    index: usize,
+    running: Option<mpsc::Receiver<(usize, TestResult)>>,
+    message: String,
+    compare: Compare,
+    // The top row of the diff view of the selected case
+    diff: Option<usize>,
+}
+
+impl TestCases {
+    pub fn new(buf: &Buffer) -> Option<Self> {
+        let dir = test_case::test_dir(buf.path()?)?;
+        let mut res = Self {
+            dir,
+            cases: Vec::new(),
+            results: Vec::new(),
+            index: 0,
+            running: None,
+            message: String::new(),
+            compare: Compare::default(),
+            diff: None,
+        };
+        res.reload();
+        Some(res)
+    }
+
+    fn reload(&mut self) {
+        self.cases = test_case::list(&self.dir);
+        self.results = self.cases.iter().map(|_| None).collect();
+        self.running = None;
+        self.index = min(self.index, self.cases.len().saturating_sub(1));
+    }
+
+    fn run_all(&mut self, buf: &mut Buffer, is_optimize: bool) {
+        let path = if let Some(path) = buf.path().map(PathBuf::from) {
+            path
+        } else {
+            return;
+        };
+        buf.format();
+        buf.save(is_optimize);
+        buf.wait_compile_message();
+        if buf.last_compile_success() == Some(false) {
+            self.message = "Compile error".into();
+            return;
+        }
+        let program = if let Some(program) = test_case::program(&path) {
+            program
+        } else {
+            return;
+        };
+
+        self.reload();
+        self.message.clear();
+        let cases = self.cases.clone();
+        let limits = buf.limits();
+        let compare = self.compare;
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for (i, case) in cases.iter().enumerate() {
+                let result = test_case::run(&program, case, &limits, compare);
+                let result = result.unwrap_or_else(|e| TestResult {
+                    verdict: Verdict::RE,
+                    time: Default::default(),
+                    memory: None,
+                    stdout: String::new(),
+                    stderr: e.to_string(),
+                    expected: String::new(),
+                });
+                if tx.send((i, result)).is_err() {
+                    return;
+                }
+            }
+        });
+        self.running = Some(rx);
+    }
+
+    fn selected(&self) -> Option<&TestCase> {
+        self.cases.get(self.index)
+    }
+
+    fn receive(&mut self) {
+        if let Some(running) = self.running.as_ref() {
+            while let Ok((i, mut result)) = running.try_recv() {
+                result.judge(self.compare);
+                self.results[i] = Some(result);
+            }
+        }
+    }
+
+    fn toggle_compare(&mut self) {
+        self.compare = self.compare.next();
+        for result in self.results.iter_mut().flatten() {
+            result.judge(self.compare);
+        }
+        self.message = format!("Compare: {}", self.compare.name());
+    }
+
+    // Side by side diff of the output and the expected one
+    fn draw_diff(&mut self, mut view: draw::TermView) {
+        let (result, case) = match (&self.results[self.index], &self.cases[self.index]) {
+            (Some(result), case) => (result, case),
+            _ => return,
+        };
+        let actual: Vec<&str> = result.stdout.lines().collect();
+        let expected: Vec<&str> = result.expected.lines().collect();
+        let stderr: Vec<&str> = result.stderr.lines().collect();
+        let len = max(actual.len(), expected.len());
+        let mismatch = test_case::first_mismatch(&result.stdout, &result.expected, self.compare);
+
+        let height = view.height();
+        let width = view.width();
+        let half = width.saturating_sub(1) / 2;
+        let num_width = len.to_string().len() + 1;
+        {
+            let mut header = view.view((0, 0), 1, width);
+            header.puts(&format!("Case {} ", case.name), draw::styles::DEFAULT);
+            let style = if result.verdict == Verdict::AC {
+                draw::styles::ACCEPTED
+            } else {
+                draw::styles::HIGHLIGHT
+            };
+            header.puts(result.verdict.as_str(), style);
+            if let Some(row) = mismatch {
+                header.puts(
+                    &format!("  First mismatch at line {}", row + 1),
+                    draw::styles::DEFAULT,
+                );
+            }
+        }
+
+        // Rows of the diff followed by stderr
+        let total = if stderr.is_empty() {
+            len
+        } else {
+            len + 1 + stderr.len()
+        };
+        let body = height.saturating_sub(2);
+        let top = min(self.diff.unwrap_or(0), total.saturating_sub(body));
+        self.diff = Some(top);
+        for (y, row) in (top..total).take(body).enumerate() {
+            if row >= len {
+                let mut line = view.view((y + 1, 0), 1, width);
+                if row == len {
+                    line.puts("stderr:", draw::styles::UI);
+                } else {
+                    for c in stderr[row - len - 1].chars() {
+                        line.put_inline(c, draw::styles::UI, None);
+                    }
+                }
+                continue;
+            }
+            let a = actual.get(row).cloned().unwrap_or("");
+            let b = expected.get(row).cloned().unwrap_or("");
+            let (mark_a, mark_b) = test_case::diff_line(a, b, self.compare);
+            let num_style =
+                if mismatch == Some(row) || mark_a.contains(&true) || mark_b.contains(&true) {
+                    draw::styles::HIGHLIGHT
+                } else {
+                    draw::styles::UI
+                };
+            for (x, line, marks, exists) in [
+                (0, a, mark_a, row < actual.len()),
+                (half + 1, b, mark_b, row < expected.len()),
+            ]
+            .iter()
+            {
+                let mut view = view.view((y + 1, *x), 1, half);
+                if !exists {
+                    view.puts("~", draw::styles::UI);
+                    continue;
+                }
+                view.puts(&format!("{:>w$} ", row + 1, w = num_width - 1), num_style);
+                for (c, &mark) in line.chars().zip(marks.iter()) {
+                    let style = if mark {
+                        draw::styles::DIFF
+                    } else {
+                        draw::styles::DEFAULT
+                    };
+                    view.put_inline(c, style, None);
+                }
+            }
+            view.view((y + 1, half), 1, 1).puts("|", draw::styles::UI);
+        }
+        {
+            let mut footer = view.view((height - 1, 0), 1, width);
+            footer.puts(
+                &format!("Output | Expected  Compare: {} ", self.compare.name()),
+                draw::styles::FOOTER,
+            );
+            footer.puts(
+                "[j/k: Scroll] [w: Compare] [Esc: Return]",
+                draw::styles::FOOTER,
+            );
+        }
+    }
+}
+
+impl Mode for TestCases {
+    fn event(&mut self, buf: &mut Buffer, event: termion::event::Event) -> Transition {
+        if let Some(row) = self.diff {
+            match event {
+                Event::Key(Key::Esc) | Event::Key(Key::Char('q')) => self.diff = None,
+                Event::Key(Key::Char('j')) | Event::Key(Key::Down) => self.diff = Some(row + 1),
+                Event::Key(Key::Char('k')) | Event::Key(Key::Up) => {
+                    self.diff = Some(row.saturating_sub(1))
+                }
+                Event::Key(Key::Char('w')) => self.toggle_compare(),
+                _ => {}
+            }
+            return Transition::Nothing;
+        }
+        match event {
+            Event::Key(Key::Esc) => return Transition::Return(None, false),
+            Event::Key(Key::Char('j')) | Event::Key(Key::Down) => {
+                self.index = min(self.index + 1, self.cases.len().saturating_sub(1));
+            }
+            Event::Key(Key::Char('k')) | Event::Key(Key::Up) => {
+                self.index = self.index.saturating_sub(1);
+            }
+            Event::Key(Key::Char('a')) => match test_case::add(&self.dir) {
+                Ok(case) => {
+                    self.reload();
+                    self.index = self.cases.len() - 1;
+                    return Transition::OpenNewTab(case.input);
+                }
+                Err(e) => self.message = format!("Failed to add: {}", e),
+            },
+            Event::Key(Key::Char('i')) => {
+                if let Some(case) = self.selected() {
+                    return Transition::OpenNewTab(case.input.clone());
+                }
+            }
+            Event::Key(Key::Char('o')) => {
+                if let Some(case) = self.selected() {
+                    return Transition::OpenNewTab(case.output.clone());
+                }
+            }
+            Event::Key(Key::Char('d')) => {
+                if let Some(case) = self.selected() {
+                    if let Err(e) = test_case::remove(case) {
+                        self.message = format!("Failed to delete: {}", e);
+                    }
+                    self.reload();
+                }
+            }
+            Event::Key(Key::Char('r')) | Event::Key(Key::Char('R')) => {
+                let is_optimize = event == Event::Key(Key::Char('R'));
+                self.run_all(buf, is_optimize);
+            }
+            Event::Key(Key::Char('w')) => self.toggle_compare(),
+            Event::Key(Key::Char('\n')) => {
+                self.receive();
+                if let Some(Some(result)) = self.results.get(self.index) {
+                    let row =
+                        test_case::first_mismatch(&result.stdout, &result.expected, self.compare)
+                            .unwrap_or(0);
+                    self.diff = Some(row.saturating_sub(3));
+                }
+            }
+            _ => {}
+        }
+        Transition::Nothing
+    }
+
+    fn draw(&mut self, _buf: &mut Buffer, mut view: draw::TermView) -> draw::CursorState {
+        self.receive();
+        if self.diff.is_some() {
+            self.draw_diff(view);
+            return draw::CursorState::Hide;
+        }
+
+        let height = view.height();
+        let width = view.width();
+        {
+            let mut view = view.view((0, 0), height - 1, width);
+            if self.cases.is_empty() {
+                view.puts(
+                    &format!("No test cases in {}", self.dir.display()),
+                    draw::styles::UI,
+                );
+            }
+            for (i, case) in self.cases.iter().enumerate() {
+                let mut name_style = draw::styles::DEFAULT;
+                if i == self.index {
+                    name_style.bg = draw::Color {
+                        r: 0x44,
+                        g: 0x44,
+                        b: 0x44,
+                    };
+                }
+                view.puts(&format!("{:>4} ", case.name), name_style);
+                match &self.results[i] {
+                    Some(result) => {
+                        let style = if result.verdict == Verdict::AC {
+                            draw::styles::ACCEPTED
+                        } else {
+                            draw::styles::HIGHLIGHT
+                        };
+                        view.puts(&format!("{:<4}", result.verdict.as_str()), style);
+                        view.puts(
+                            &format!("{:>6} ms", result.time.as_millis()),
+                            draw::styles::DEFAULT,
+                        );
+                        if let Some(memory) = result.memory {
+                            view.puts(
+                                &format!("{:>8.1} MB", memory as f64 / (1 << 20) as f64),
+                                draw::styles::DEFAULT,
+                            );
+                        }
+                    }
+                    None if self.running.is_some() => view.puts("...", draw::styles::UI),
+                    None => view.puts("-", draw::styles::UI),
+                }
+                view.newline();
+            }
+        }
+        {
+            let mut footer = view.view((height - 1, 0), 1, width);
+            let done = self.results.iter().filter(|r| r.is_some()).count();
+            if self.running.is_some() {
+                let accepted = self
+                    .results
+                    .iter()
+                    .filter(|r| r.as_ref().map(|r| r.verdict) == Some(Verdict::AC))
+                    .count();
+                footer.puts(
+                    &format!("{}/{} AC ", accepted, self.cases.len()),
+                    draw::styles::FOOTER,
+                );
+                if done < self.cases.len() {
+                    footer.puts("Running... ", draw::styles::FOOTER);
+                }
+            }
+            if !self.message.is_empty() {
+                footer.puts(&format!("{} ", self.message), draw::styles::FOOTER);
+            }
+            footer.puts(
+                "[a: Add] [i: Input] [o: Output] [d: Delete] [r/R: Run all] [Enter: Diff] [w: Compare] [Esc: Return]",
+                draw::styles::FOOTER,
+            );
+        }
+        draw::CursorState::Hide
+    }
+}
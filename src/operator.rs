@@ -0,0 +1,426 @@
+use std::borrow::Cow;
+use std::ops::RangeInclusive;
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+use crate::buffer::Buffer;
+use crate::config::types::CommentConfig;
+use crate::core::{Core, Cursor, CursorRange};
+use crate::indent;
+use crate::ropey_util::RopeExt;
+use crate::text_object;
+
+fn line(core: &Core, row: usize) -> String {
+    Cow::from(core.buffer().l(row)).into_owned()
+}
+
+// Replace the text of `row` without the line end
+fn set_line(core: &mut Core, row: usize, text: &str) {
+    let len = core.buffer().l(row).len_chars();
+    if len > 0 {
+        core.delete_range(CursorRange(
+            Cursor { row, col: 0 },
+            Cursor { row, col: len - 1 },
+        ));
+    }
+    core.set_cursor(Cursor { row, col: 0 });
+    for c in text.chars() {
+        core.insert(c);
+    }
+}
+
+// > and <
+pub fn shift(buf: &mut Buffer, rows: RangeInclusive<usize>, right: bool) {
+    let indent_width = buf.indent_width();
+    for row in rows.clone() {
+        let line = line(&buf.core, row);
+        if line.trim().is_empty() {
+            continue;
+        }
+        let width = indent::indent_width_of(&line, indent_width);
+        let width = if right {
+            width + indent_width
+        } else {
+            width.saturating_sub(indent_width)
+        };
+        let content = &line[indent::indent_len(&line)..];
+        let shifted = format!("{}{}", buf.indent_string(width), content);
+        set_line(&mut buf.core, row, &shifted);
+    }
+    move_to_first_non_blank(&mut buf.core, *rows.start());
+}
+
+// = by the indent rules from the previous line
+pub fn reindent(buf: &mut Buffer, rows: RangeInclusive<usize>) {
+    let indent_width = buf.indent_width();
+    for row in rows.clone() {
+        let line = line(&buf.core, row);
+        let content = line.trim_start();
+        if content.is_empty() {
+            set_line(&mut buf.core, row, "");
+            continue;
+        }
+        let prev = (0..row)
+            .rev()
+            .map(|r| self::line(&buf.core, r))
+            .find(|l| !l.trim().is_empty());
+        let indent = prev
+            .map(|l| buf.indent_rules().indent(&l, content, indent_width))
+            .unwrap_or(0);
+        let indented = format!("{}{}", buf.indent_string(indent), content);
+        if indented != line {
+            set_line(&mut buf.core, row, &indented);
+        }
+    }
+    move_to_first_non_blank(&mut buf.core, *rows.start());
+}
+
+// gq. Paragraphs separated by blank lines are wrapped at `width` keeping their indent.
+pub fn rewrap(buf: &mut Buffer, rows: RangeInclusive<usize>, width: usize) {
+    let mut paragraphs = Vec::new();
+    let mut start = None;
+    for row in rows.clone() {
+        let is_blank = line(&buf.core, row).trim().is_empty();
+        match (start, is_blank) {
+            (None, false) => start = Some(row),
+            (Some(s), true) => {
+                paragraphs.push(s..=row - 1);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        paragraphs.push(s..=*rows.end());
+    }
+
+    // From the bottom to keep rows of the rest
+    for paragraph in paragraphs.into_iter().rev() {
+        let lines: Vec<String> = paragraph.clone().map(|r| line(&buf.core, r)).collect();
+        let indent = lines[0][..indent::indent_len(&lines[0])].to_string();
+        let mut wrapped = Vec::new();
+        let mut current = String::new();
+        for word in lines.iter().flat_map(|l| l.split_whitespace()) {
+            if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > width {
+                wrapped.push(std::mem::take(&mut current));
+            }
+            if current.is_empty() {
+                current.push_str(&indent);
+            } else {
+                current.push(' ');
+            }
+            current.push_str(word);
+        }
+        wrapped.push(current);
+
+        let (first, last) = (*paragraph.start(), *paragraph.end());
+        let len = buf.core.buffer().l(last).len_chars();
+        buf.core.delete_range(CursorRange(
+            Cursor { row: first, col: 0 },
+            Cursor {
+                row: last,
+                col: len - 1,
+            },
+        ));
+        buf.core.set_cursor(Cursor { row: first, col: 0 });
+        for c in wrapped.join("\n").chars() {
+            buf.core.insert(c);
+        }
+    }
+    move_to_first_non_blank(&mut buf.core, *rows.start());
+}
+
+// gc. Lines are commented at the least indent unless all of them are commented.
+// The block comment is used if the language has no line comment.
+pub fn toggle_comment(buf: &mut Buffer, rows: RangeInclusive<usize>, comment: &CommentConfig) {
+    let (left, right) = match (&comment.line, &comment.block) {
+        (Some(line), _) => (line.as_str(), ""),
+        (None, Some((l, r))) => (l.as_str(), r.as_str()),
+        (None, None) => return,
+    };
+    let lines: Vec<(usize, String)> = rows
+        .clone()
+        .map(|row| (row, line(&buf.core, row)))
+        .filter(|(_, s)| !s.trim().is_empty())
+        .collect();
+    let is_commented = |s: &str| {
+        let s = s.trim();
+        s.len() >= left.len() + right.len() && s.starts_with(left) && s.ends_with(right)
+    };
+
+    if lines.iter().all(|(_, s)| is_commented(s)) {
+        for (row, s) in lines {
+            let (indent, body) = s.split_at(indent::indent_len(&s));
+            let body = body.trim_end();
+            let body = &body[left.len()..body.len() - right.len()];
+            let body = body.strip_prefix(' ').unwrap_or(body);
+            let body = if right.is_empty() {
+                body
+            } else {
+                body.strip_suffix(' ').unwrap_or(body)
+            };
+            set_line(&mut buf.core, row, &format!("{}{}", indent, body));
+        }
+    } else {
+        let min_indent = lines
+            .iter()
+            .map(|(_, s)| indent::indent_len(s))
+            .min()
+            .unwrap_or(0);
+        for (row, s) in lines {
+            let (indent, body) = s.split_at(min_indent);
+            let commented = if right.is_empty() {
+                format!("{}{} {}", indent, left, body)
+            } else {
+                format!("{}{} {} {}", indent, left, body, right)
+            };
+            set_line(&mut buf.core, row, &commented);
+        }
+    }
+    move_to_first_non_blank(&mut buf.core, *rows.start());
+}
+
+// Pairs of the surround commands. An opening bracket means the pair with spaces inside and
+// a closing one or an alias like `b` means the tight pair.
+pub fn surround_pair(c: char) -> Option<(char, char)> {
+    match c {
+        '(' | ')' | 'b' => Some(('(', ')')),
+        '{' | '}' | 'B' => Some(('{', '}')),
+        '[' | ']' | 'r' => Some(('[', ']')),
+        '<' | '>' | 'a' => Some(('<', '>')),
+        '"' | '\'' | '`' => Some((c, c)),
+        _ => None,
+    }
+}
+
+fn is_spaced(c: char) -> bool {
+    c == '(' || c == '{' || c == '['
+}
+
+fn insert_str(core: &mut Core, s: &str) {
+    for c in s.chars() {
+        core.insert(c);
+    }
+}
+
+// ys and S. A char without a pair surrounds by itself.
+pub fn add_surround(core: &mut Core, range: CursorRange, c: char) {
+    let (l, r) = surround_pair(c).unwrap_or((c, c));
+    let space = if is_spaced(c) { " " } else { "" };
+    core.set_cursor(range.r());
+    core.cursor_inc();
+    insert_str(core, &format!("{}{}", space, r));
+    core.set_cursor(range.l());
+    insert_str(core, &format!("{}{}", l, space));
+    core.set_cursor(range.l());
+}
+
+// ds and cs. The pair `target` around the cursor is replaced with `new` or deleted.
+// An opening bracket as `target` removes the spaces inside too.
+pub fn change_surround(core: &mut Core, target: char, new: Option<char>) -> bool {
+    let range = match text_object::surrounding_range(target, core) {
+        Some(range) => range,
+        None => return false,
+    };
+    let (l, r) = (range.l(), range.r());
+    let (mut inner_l, mut inner_r) = (l, r);
+    if is_spaced(target) {
+        let is_space = |t: Cursor| matches!(core.char_at(t), Some(' ') | Some('\t'));
+        while inner_r.col > 0 {
+            let prev = Cursor {
+                row: inner_r.row,
+                col: inner_r.col - 1,
+            };
+            if prev <= l || !is_space(prev) {
+                break;
+            }
+            inner_r = prev;
+        }
+        loop {
+            let next = Cursor {
+                row: inner_l.row,
+                col: inner_l.col + 1,
+            };
+            if next >= inner_r || !is_space(next) {
+                break;
+            }
+            inner_l = next;
+        }
+    }
+
+    // The right side first to keep the position of the left
+    let pair = new.map(|c| {
+        let (nl, nr) = surround_pair(c).unwrap_or((c, c));
+        let space = if is_spaced(c) { " " } else { "" };
+        (format!("{}{}", nl, space), format!("{}{}", space, nr))
+    });
+    core.delete_range(CursorRange(inner_r, r));
+    if let Some((_, right)) = &pair {
+        core.set_cursor(inner_r);
+        insert_str(core, right);
+    }
+    core.delete_range(CursorRange(l, inner_l));
+    if let Some((left, _)) = &pair {
+        core.set_cursor(l);
+        insert_str(core, left);
+    }
+    core.set_cursor(l);
+    true
+}
+
+// Ctrl-a and Ctrl-x. Adds `delta` to the number at or after `cursor` in the line keeping its
+// radix, `_` separators and type suffix like `1_000usize`. The cursor moves to its last digit.
+pub fn add_to_number(core: &mut Core, cursor: Cursor, delta: i128) -> bool {
+    let line = line(core, cursor.row);
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    // Compiled once as Visual Ctrl-a calls this for each line
+    let regex = REGEX.get_or_init(|| {
+        Regex::new(r"(-?)(0[xX][0-9a-fA-F_]+|0[bB][01_]+|[0-9][0-9_]*)([iu](8|16|32|64|128|size))?")
+            .unwrap()
+    });
+    let col_of = |i: usize| line[..i].chars().count();
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    // Digits in a word like `u64` are not numbers
+    let m = match regex
+        .captures_iter(&line)
+        .filter(|m| {
+            !line[..m.get(2).unwrap().start()]
+                .chars()
+                .last()
+                .is_some_and(is_word)
+        })
+        .find(|m| col_of(m.get(0).unwrap().end()) > cursor.col)
+    {
+        Some(m) => m,
+        None => return false,
+    };
+
+    let body = m.get(2).unwrap();
+    // `-` after a word is not a sign like `n-1`
+    let is_negative = !m[1].is_empty()
+        && !line[..m.get(1).unwrap().start()]
+            .chars()
+            .last()
+            .is_some_and(|c| is_word(c) || c == ')' || c == ']');
+    let start = if is_negative {
+        m.get(1).unwrap().start()
+    } else {
+        body.start()
+    };
+    // Underscores before the suffix like `1_usize` are kept as they are
+    let number = body.as_str().trim_end_matches('_');
+    let end = body.start() + number.len();
+    let (prefix, digits, radix) = match number.get(..2) {
+        Some("0x") | Some("0X") => (&number[..2], &number[2..], 16),
+        Some("0b") | Some("0B") => (&number[..2], &number[2..], 2),
+        _ => ("", number, 10),
+    };
+    let value = match i128::from_str_radix(&digits.replace('_', ""), radix) {
+        Ok(value) => value,
+        Err(_) => return false,
+    };
+    let value = if is_negative { -value } else { value };
+    let value = match value.checked_add(delta) {
+        Some(value) => value,
+        None => return false,
+    };
+
+    let abs = value.unsigned_abs();
+    let mut new_digits = match radix {
+        16 if digits.chars().any(|c| c.is_ascii_uppercase()) => format!("{:X}", abs),
+        16 => format!("{:x}", abs),
+        2 => format!("{:b}", abs),
+        _ => abs.to_string(),
+    };
+    // Hex and binary numbers keep their width like `0x00ff`
+    let width = digits.chars().filter(|&c| c != '_').count();
+    if radix != 10 && new_digits.len() < width {
+        new_digits = format!("{}{}", "0".repeat(width - new_digits.len()), new_digits);
+    }
+    // Separators in the same groups like `1_000_000`
+    if let Some(group) = digits.rfind('_').map(|i| digits.len() - i - 1) {
+        if group > 0 {
+            let chars: Vec<char> = new_digits.chars().collect();
+            let groups: Vec<String> = chars
+                .rchunks(group)
+                .rev()
+                .map(|g| g.iter().collect())
+                .collect();
+            new_digits = groups.join("_");
+        }
+    }
+    let new = format!(
+        "{}{}{}",
+        if value < 0 { "-" } else { "" },
+        prefix,
+        new_digits
+    );
+
+    let row = cursor.row;
+    let (l, r) = (col_of(start), col_of(end));
+    core.delete_range(CursorRange(
+        Cursor { row, col: l },
+        Cursor { row, col: r - 1 },
+    ));
+    core.set_cursor(Cursor { row, col: l });
+    insert_str(core, &new);
+    core.set_cursor(Cursor {
+        row,
+        col: l + new.chars().count() - 1,
+    });
+    true
+}
+
+// gu, gU and g~
+pub fn change_case<F: Fn(char) -> char>(core: &mut Core, range: CursorRange, f: F) {
+    let mut t = range.l();
+    loop {
+        if let Some(c) = core.char_at(t) {
+            let d = f(c);
+            if c != d {
+                core.set_cursor(t);
+                core.replace(d);
+            }
+        }
+        if t >= range.r() {
+            break;
+        }
+        if let Some(next) = core.next_cursor(t) {
+            t = next;
+        } else {
+            break;
+        }
+    }
+    core.set_cursor(range.l());
+}
+
+// Case conversion which keeps the length like Vim
+pub fn to_lower(c: char) -> char {
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(l), None) => l,
+        _ => c,
+    }
+}
+
+pub fn to_upper(c: char) -> char {
+    let mut upper = c.to_uppercase();
+    match (upper.next(), upper.next()) {
+        (Some(u), None) => u,
+        _ => c,
+    }
+}
+
+pub fn toggle_case(c: char) -> char {
+    if c.is_lowercase() {
+        to_upper(c)
+    } else {
+        to_lower(c)
+    }
+}
+
+fn move_to_first_non_blank(core: &mut Core, row: usize) {
+    core.set_cursor(Cursor { row, col: 0 });
+    core.first_non_blank();
+}
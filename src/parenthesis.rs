@@ -1,3 +1,5 @@
 pub const PARENTHESIS_PAIRS: [(char, char); 3] = [('{', '}'), ('(', ')'), ('[', ']')];
 pub const PARENTHESIS_LEFTS: [char; 3] = ['{', '(', '['];
 pub const PARENTHESIS_RIGHTS: [char; 3] = ['}', ')', ']'];
+// Used if the language has no `auto_pairs` config
+pub const AUTO_PAIRS: [(char, char); 4] = [('(', ')'), ('{', '}'), ('[', ']'), ('"', '"')];
@@ -0,0 +1,184 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use termion::event::Event;
+
+use crate::clipboard;
+
+#[derive(Clone, Debug, Default)]
+pub struct Yank {
+    pub insert_newline: bool,
+    // Put as a rectangle. Yanked in blockwise Visual mode.
+    pub block: bool,
+    pub content: String,
+}
+
+impl Yank {
+    // Used by uppercase registers like `"A`
+    fn append(&mut self, other: Yank) {
+        if self.content.is_empty() {
+            *self = other;
+            return;
+        }
+        if self.block && other.block {
+            // Rows of the other block go below
+            self.content.push('\n');
+        } else if self.insert_newline || other.insert_newline {
+            self.content.push('\n');
+            self.insert_newline = true;
+        }
+        self.block = self.block && other.block;
+        self.content.push_str(&other.content);
+    }
+}
+
+// Registers like Vim
+// "a - "z: Named registers. "A - "Z appends to them.
+// "0: Last yank
+// "1 - "9: Last deleted lines
+// "-: Last deleted text within a line
+// "_: Black hole
+// "+: Clipboard
+// Keyboard macros recorded by `q` are stored separately from texts.
+#[derive(Default)]
+pub struct Registers {
+    selected: Option<char>,
+    unnamed: Yank,
+    named: BTreeMap<char, Yank>,
+    last_yank: Yank,
+    small_delete: Yank,
+    deleted: VecDeque<Yank>,
+    macros: BTreeMap<char, Vec<Event>>,
+    recording: Option<(char, Vec<Event>)>,
+    last_macro: Option<char>,
+}
+
+impl Registers {
+    const DELETED_LEN: usize = 9;
+
+    pub fn is_valid(register: char) -> bool {
+        register.is_ascii_alphanumeric() || ['"', '-', '_', '+'].contains(&register)
+    }
+
+    // Register for the next operation. None for the unnamed register.
+    pub fn select(&mut self, register: Option<char>) {
+        self.selected = register;
+    }
+
+    // Store text yanked by `y`
+    pub fn yank(&mut self, yank: Yank) -> Result<(), failure::Error> {
+        self.store(yank, false)
+    }
+
+    // Store text deleted by `d`, `c` or `x`
+    pub fn delete(&mut self, yank: Yank) -> Result<(), failure::Error> {
+        self.store(yank, true)
+    }
+
+    fn store(&mut self, yank: Yank, is_delete: bool) -> Result<(), failure::Error> {
+        match self.selected {
+            Some('_') => {}
+            Some('+') => {
+                clipboard::clipboard_copy(&yank.content)?;
+                self.unnamed = yank;
+            }
+            Some(c) if c.is_ascii_lowercase() => {
+                self.named.insert(c, yank.clone());
+                self.unnamed = yank;
+            }
+            Some(c) if c.is_ascii_uppercase() => {
+                let named = self.named.entry(c.to_ascii_lowercase()).or_default();
+                named.append(yank);
+                self.unnamed = named.clone();
+            }
+            Some('0') => {
+                self.last_yank = yank.clone();
+                self.unnamed = yank;
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let i = c.to_digit(10).unwrap() as usize - 1;
+                while self.deleted.len() <= i {
+                    self.deleted.push_back(Yank::default());
+                }
+                self.deleted[i] = yank.clone();
+                self.unnamed = yank;
+            }
+            Some('-') => {
+                self.small_delete = yank.clone();
+                self.unnamed = yank;
+            }
+            _ => {
+                if !is_delete {
+                    self.last_yank = yank.clone();
+                } else if yank.insert_newline || yank.content.contains('\n') {
+                    self.deleted.push_front(yank.clone());
+                    self.deleted.truncate(Self::DELETED_LEN);
+                } else {
+                    self.small_delete = yank.clone();
+                }
+                self.unnamed = yank;
+            }
+        }
+        Ok(())
+    }
+
+    // Content of the selected register to put
+    pub fn get(&self) -> Option<Yank> {
+        match self.selected {
+            Some('_') => None,
+            Some('+') => clipboard::clipboard_paste().ok().map(|content| Yank {
+                insert_newline: false,
+                block: false,
+                content,
+            }),
+            Some(c) if c.is_ascii_alphabetic() => self.named.get(&c.to_ascii_lowercase()).cloned(),
+            Some('0') => Some(self.last_yank.clone()),
+            Some(c) if c.is_ascii_digit() => {
+                let i = c.to_digit(10).unwrap() as usize - 1;
+                self.deleted.get(i).cloned()
+            }
+            Some('-') => Some(self.small_delete.clone()),
+            _ => Some(self.unnamed.clone()),
+        }
+    }
+
+    // Uppercase register appends to the macro
+    pub fn start_recording(&mut self, register: char) {
+        let lower = register.to_ascii_lowercase();
+        let events = if register.is_ascii_uppercase() {
+            self.macros.get(&lower).cloned().unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        self.recording = Some((lower, events));
+    }
+
+    pub fn recording(&self) -> Option<char> {
+        self.recording.as_ref().map(|(register, _)| *register)
+    }
+
+    pub fn record(&mut self, event: Event) {
+        if let Some((_, events)) = self.recording.as_mut() {
+            events.push(event);
+        }
+    }
+
+    // The last recorded event is `q` which stops recording
+    pub fn stop_recording(&mut self) {
+        if let Some((register, mut events)) = self.recording.take() {
+            events.pop();
+            self.macros.insert(register, events);
+        }
+    }
+
+    // `@` means the last played macro
+    pub fn get_macro(&mut self, register: char) -> Option<Vec<Event>> {
+        let register = if register == '@' {
+            self.last_macro?
+        } else {
+            register.to_ascii_lowercase()
+        };
+        let events = self.macros.get(&register).cloned()?;
+        self.last_macro = Some(register);
+        Some(events)
+    }
+}
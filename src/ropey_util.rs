@@ -2,6 +2,8 @@ use ropey::{Rope, RopeSlice};
 
 pub trait RopeSliceExt {
     fn trim_end(self) -> Self;
+    // Trim only the last line break to keep blank lines at the end
+    fn trim_last_line_end(self) -> Self;
 }
 
 pub trait RopeExt {
@@ -24,6 +26,18 @@ impl<'a> RopeSliceExt for RopeSlice<'a> {
 
         self.slice(..i)
     }
+
+    fn trim_last_line_end(self) -> Self {
+        let mut i = self.len_chars();
+        if i > 0 && is_line_end(self.char(i - 1)) {
+            i -= 1;
+            while i > 0 && self.char(i - 1) == '\r' {
+                i -= 1;
+            }
+        }
+
+        self.slice(..i)
+    }
 }
 
 impl RopeExt for Rope {
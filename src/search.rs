@@ -0,0 +1,157 @@
+use std::borrow::Cow;
+use std::fs;
+use std::path::PathBuf;
+
+use regex::{Regex, RegexBuilder};
+use ropey::Rope;
+
+use crate::core::Cursor;
+use crate::ropey_util::RopeExt;
+
+// Smart case: Case insensitive unless the pattern has an uppercase character.
+// An invalid regex like `foo(` while typing is searched literally.
+pub fn build_regex(pattern: &str) -> Option<Regex> {
+    if pattern.is_empty() {
+        return None;
+    }
+    let case_insensitive = !pattern.chars().any(char::is_uppercase);
+    RegexBuilder::new(pattern)
+        .case_insensitive(case_insensitive)
+        .build()
+        .or_else(|_| {
+            RegexBuilder::new(&regex::escape(pattern))
+                .case_insensitive(case_insensitive)
+                .build()
+        })
+        .ok()
+}
+
+// Matches in a line as half-open ranges of char index
+pub fn find_in_line(regex: &Regex, line: &str) -> Vec<(usize, usize)> {
+    let mut res = Vec::new();
+    let mut col = 0;
+    let mut byte = 0;
+    for m in regex.find_iter(line) {
+        if m.start() == m.end() {
+            continue;
+        }
+        col += line[byte..m.start()].chars().count();
+        let l = col;
+        col += m.as_str().chars().count();
+        byte = m.end();
+        res.push((l, col));
+    }
+    res
+}
+
+fn line_matches(buffer: &Rope, regex: &Regex, row: usize) -> Vec<(usize, usize)> {
+    find_in_line(regex, &Cow::from(buffer.l(row)))
+}
+
+// The first match after `from`. Wraps around the end of the buffer.
+pub fn find_next(buffer: &Rope, regex: &Regex, from: Cursor) -> Option<Cursor> {
+    let len_lines = buffer.len_lines();
+    for k in 0..=len_lines {
+        let row = (from.row + k) % len_lines;
+        let found = line_matches(buffer, regex, row)
+            .into_iter()
+            .map(|(l, _)| l)
+            .find(|&col| {
+                if k == 0 {
+                    col > from.col
+                } else if k == len_lines {
+                    col <= from.col
+                } else {
+                    true
+                }
+            });
+        if let Some(col) = found {
+            return Some(Cursor { row, col });
+        }
+    }
+    None
+}
+
+// The last match before `from`. Wraps around the start of the buffer.
+pub fn find_prev(buffer: &Rope, regex: &Regex, from: Cursor) -> Option<Cursor> {
+    let len_lines = buffer.len_lines();
+    for k in 0..=len_lines {
+        let row = (from.row + len_lines - k % len_lines) % len_lines;
+        let found = line_matches(buffer, regex, row)
+            .into_iter()
+            .map(|(l, _)| l)
+            .rev()
+            .find(|&col| {
+                if k == 0 {
+                    col < from.col
+                } else if k == len_lines {
+                    col >= from.col
+                } else {
+                    true
+                }
+            });
+        if let Some(col) = found {
+            return Some(Cursor { row, col });
+        }
+    }
+    None
+}
+
+// Search history shared by all buffers through a file
+#[derive(Default)]
+pub struct SearchHistory {
+    entries: Vec<String>,
+    path: Option<PathBuf>,
+}
+
+impl SearchHistory {
+    const MAX_LEN: usize = 100;
+
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|mut p| {
+            p.push("acc");
+            p.push("search_history");
+            p
+        })
+    }
+
+    pub fn with_path(path: PathBuf) -> Self {
+        let mut res = Self {
+            entries: Vec::new(),
+            path: Some(path),
+        };
+        res.reload();
+        res
+    }
+
+    // Load entries added by other buffers
+    pub fn reload(&mut self) {
+        if let Some(path) = self.path.as_ref() {
+            if let Ok(s) = fs::read_to_string(path) {
+                self.entries = s.lines().map(String::from).collect();
+            }
+        }
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    pub fn push(&mut self, query: &str) {
+        self.entries.retain(|e| e != query);
+        self.entries.push(query.to_string());
+        if self.entries.len() > Self::MAX_LEN {
+            let len = self.entries.len() - Self::MAX_LEN;
+            self.entries.drain(..len);
+        }
+
+        if let Some(path) = self.path.as_ref() {
+            if let Some(parent) = path.parent() {
+                let _ = fs::create_dir_all(parent);
+            }
+            let mut s = self.entries.join("\n");
+            s.push('\n');
+            let _ = fs::write(path, s);
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+
+use crate::compiler::Compiler;
+use crate::config::types::CompilerConfig;
+use crate::test_case::{self, Compare, Limits, TestCase, Verdict};
+
+// A source next to `path` like `a_gen.rs` for `a.rs` and `_gen`
+pub fn sibling(path: &Path, suffix: &str) -> Option<PathBuf> {
+    let mut name = path.file_stem()?.to_os_string();
+    name.push(suffix);
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    Some(path.with_file_name(name))
+}
+
+// Sources of the stress test of `a.rs` are `a_gen.rs` and `a_brute.rs`.
+// The generator gets the iteration number as a seed by its argument.
+pub fn generator(path: &Path) -> Option<PathBuf> {
+    sibling(path, "_gen")
+}
+
+pub fn brute(path: &Path) -> Option<PathBuf> {
+    sibling(path, "_brute")
+}
+
+// The program is newer than its source
+pub fn is_built(path: &Path) -> bool {
+    let modified = |p: &Path| fs::metadata(p).and_then(|m| m.modified()).ok();
+    match (
+        test_case::program(path).and_then(|p| modified(&p)),
+        modified(path),
+    ) {
+        (Some(program), Some(source)) => program >= source,
+        _ => false,
+    }
+}
+
+// Build a helper program like the generator by the compiler of the solution unless it is built
+pub fn build(config: Option<&CompilerConfig>, source: &Path) -> Result<PathBuf, String> {
+    if !source.exists() {
+        return Err(format!("Write {}", source.display()));
+    }
+    let program = test_case::program(source).ok_or("Failed to run")?;
+    if is_built(source) {
+        return Ok(program);
+    }
+    let config = config.ok_or("No compiler")?;
+    match Compiler::new(config).compile_wait(source.to_path_buf(), true) {
+        Some(ref res) if res.success => Ok(program),
+        _ => Err(format!("Compile error in {}", source.display())),
+    }
+}
+
+pub enum Progress {
+    // Number of passed iterations
+    Passed(usize),
+    // The failing input saved as a test case
+    Found(usize, Verdict, TestCase),
+    Error(String),
+}
+
+// Built programs
+pub struct Programs {
+    pub solution: PathBuf,
+    pub generator: PathBuf,
+    pub brute: PathBuf,
+}
+
+fn execute(
+    program: &Path,
+    arg: Option<&OsStr>,
+    input: Vec<u8>,
+    limits: &Limits,
+) -> Result<String, String> {
+    let name = program.display();
+    let mut command = test_case::command(program, limits);
+    command.args(arg);
+    let execution = test_case::execute(command, input, limits)
+        .map_err(|e| format!("Failed to run {}: {}", name, e))?;
+    match execution.verdict(limits, "", Compare::Exact) {
+        Verdict::AC | Verdict::WA => Ok(execution.stdout),
+        verdict => Err(format!("{} of {}", verdict.as_str(), name)),
+    }
+}
+
+// Run the generator, the brute force and the solution until their outputs differ or `stop` is
+// set. The failing input is saved in `dir` with the output of the brute force.
+pub fn run(
+    programs: &Programs,
+    dir: &Path,
+    limits: &Limits,
+    compare: Compare,
+    stop: &AtomicBool,
+    tx: &mpsc::Sender<Progress>,
+) {
+    let mut iteration = 1;
+    while !stop.load(Ordering::Relaxed) {
+        let seed = iteration.to_string();
+        let step = || -> Result<Option<(Verdict, String, String)>, String> {
+            let input = execute(
+                &programs.generator,
+                Some(OsStr::new(&seed)),
+                Vec::new(),
+                limits,
+            )?;
+            let expected = execute(&programs.brute, None, input.clone().into_bytes(), limits)?;
+            let execution = test_case::execute(
+                test_case::command(&programs.solution, limits),
+                input.clone().into_bytes(),
+                limits,
+            )
+            .map_err(|e| format!("Failed to run the solution: {}", e))?;
+            match execution.verdict(limits, &expected, compare) {
+                Verdict::AC => Ok(None),
+                verdict => Ok(Some((verdict, input, expected))),
+            }
+        };
+        let progress = match step() {
+            Ok(None) => Progress::Passed(iteration),
+            Ok(Some((verdict, input, expected))) => match save(dir, &input, &expected) {
+                Ok(case) => Progress::Found(iteration, verdict, case),
+                Err(e) => Progress::Error(format!("Failed to save the input: {}", e)),
+            },
+            Err(e) => Progress::Error(e),
+        };
+        let is_end = !matches!(progress, Progress::Passed(_));
+        if tx.send(progress).is_err() || is_end {
+            return;
+        }
+        iteration += 1;
+    }
+}
+
+fn save(dir: &Path, input: &str, expected: &str) -> io::Result<TestCase> {
+    let case = test_case::add(dir)?;
+    fs::write(&case.input, input)?;
+    fs::write(&case.output, expected)?;
+    Ok(case)
+}
@@ -0,0 +1,542 @@
+use std::cmp::max;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_derive::Deserialize;
+
+// Test cases of `a.rs` are `a.tests/1.in` and `a.tests/1.out`
+#[derive(Clone)]
+pub struct TestCase {
+    pub name: String,
+    pub input: PathBuf,
+    pub output: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    AC,
+    WA,
+    RE,
+    TLE,
+    MLE,
+}
+
+impl Verdict {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Verdict::AC => "AC",
+            Verdict::WA => "WA",
+            Verdict::RE => "RE",
+            Verdict::TLE => "TLE",
+            Verdict::MLE => "MLE",
+        }
+    }
+}
+
+pub struct TestResult {
+    pub verdict: Verdict,
+    pub time: Duration,
+    // Peak RSS in bytes
+    pub memory: Option<u64>,
+    pub stdout: String,
+    pub stderr: String,
+    pub expected: String,
+}
+
+impl TestResult {
+    // Judge the output again by `compare`
+    pub fn judge(&mut self, compare: Compare) {
+        if self.verdict == Verdict::AC || self.verdict == Verdict::WA {
+            self.verdict = if compare.is_same(&self.stdout, &self.expected) {
+                Verdict::AC
+            } else {
+                Verdict::WA
+            };
+        }
+    }
+}
+
+// How to compare the output with the expected one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compare {
+    Exact,
+    // Ignore trailing spaces of lines and blank lines at the end
+    #[default]
+    TrailingSpace,
+    // Compare tokens separated by whitespace
+    Tokens,
+}
+
+impl Compare {
+    pub fn name(self) -> &'static str {
+        match self {
+            Compare::Exact => "exact",
+            Compare::TrailingSpace => "ignore trailing spaces",
+            Compare::Tokens => "tokens",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            Compare::Exact => Compare::TrailingSpace,
+            Compare::TrailingSpace => Compare::Tokens,
+            Compare::Tokens => Compare::Exact,
+        }
+    }
+
+    pub fn is_same(self, output: &str, expected: &str) -> bool {
+        match self {
+            Compare::Exact => output == expected,
+            Compare::TrailingSpace => normalize(output) == normalize(expected),
+            Compare::Tokens => output.split_whitespace().eq(expected.split_whitespace()),
+        }
+    }
+
+    fn is_same_line(self, a: &str, b: &str) -> bool {
+        match self {
+            Compare::Exact => a == b,
+            Compare::TrailingSpace => a.trim_end() == b.trim_end(),
+            Compare::Tokens => a.split_whitespace().eq(b.split_whitespace()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub time: Duration,
+    // In bytes
+    pub memory: Option<u64>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            time: Duration::from_secs(2),
+            memory: Some(256 << 20),
+        }
+    }
+}
+
+// No limit by 0
+pub fn megabytes(mb: u64) -> Option<u64> {
+    if mb == 0 {
+        None
+    } else {
+        Some(mb << 20)
+    }
+}
+
+// `limits.toml` in the test directory
+#[derive(Deserialize, Debug)]
+struct LimitsToml {
+    // In milliseconds
+    time_limit: Option<u64>,
+    // In megabytes
+    memory_limit: Option<u64>,
+}
+
+impl Limits {
+    // Override by `limits.toml` in `dir` for the file
+    pub fn with_file(mut self, dir: &Path) -> Self {
+        if let Some(toml) = fs::read_to_string(dir.join("limits.toml"))
+            .ok()
+            .and_then(|s| toml::from_str::<LimitsToml>(&s).ok())
+        {
+            if let Some(ms) = toml.time_limit {
+                self.time = Duration::from_millis(ms);
+            }
+            if let Some(mb) = toml.memory_limit {
+                self.memory = megabytes(mb);
+            }
+        }
+        self
+    }
+}
+
+// Exit status and peak RSS of a finished program
+pub struct Usage {
+    pub status: process::ExitStatus,
+    pub memory: Option<u64>,
+}
+
+// The command to run `program` under `limits`. The memory is limited by the address space and
+// the CPU time is limited a bit longer than the time limit in case the program is not killed.
+// SIGXCPU is sent at the soft limit.
+pub fn command(program: &Path, limits: &Limits) -> process::Command {
+    let mut command = process::Command::new(program);
+    let memory = limits.memory;
+    let cpu = limits.time.as_secs() + 2;
+    unsafe {
+        command.pre_exec(move || {
+            let set = |resource, cur: u64, max: u64| {
+                let rlimit = libc::rlimit {
+                    rlim_cur: cur as libc::rlim_t,
+                    rlim_max: max as libc::rlim_t,
+                };
+                if libc::setrlimit(resource, &rlimit) == 0 {
+                    Ok(())
+                } else {
+                    Err(io::Error::last_os_error())
+                }
+            };
+            if let Some(memory) = memory {
+                set(libc::RLIMIT_AS, memory, memory)?;
+            }
+            set(libc::RLIMIT_CPU, cpu, cpu + 1)
+        });
+    }
+    command
+}
+
+// Waits for a child by wait4 in a thread to get its peak RSS. The thread owns the PID so that
+// `kill` never signals another process reusing it after the child is reaped.
+pub struct Waiter {
+    pid: libc::pid_t,
+    reaped: Arc<Mutex<bool>>,
+    rx: mpsc::Receiver<io::Result<Usage>>,
+}
+
+impl Waiter {
+    // Take the pipes of `child` before
+    pub fn new(child: process::Child) -> Self {
+        let pid = child.id() as libc::pid_t;
+        let reaped = Arc::new(Mutex::new(false));
+        let (tx, rx) = mpsc::channel();
+        let flag = reaped.clone();
+        thread::spawn(move || {
+            let _ = tx.send(wait(pid, &flag));
+        });
+        Self { pid, reaped, rx }
+    }
+
+    pub fn kill(&self) {
+        let reaped = self.reaped.lock().unwrap();
+        if !*reaped {
+            unsafe {
+                libc::kill(self.pid, libc::SIGKILL);
+            }
+        }
+    }
+
+    pub fn try_recv(&self) -> Option<io::Result<Usage>> {
+        self.rx.try_recv().ok()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<io::Result<Usage>> {
+        self.rx.recv_timeout(timeout).ok()
+    }
+
+    pub fn recv(&self) -> io::Result<Usage> {
+        self.rx.recv().map_err(io::Error::other)?
+    }
+}
+
+// Retry on EINTR
+fn retry(mut f: impl FnMut() -> libc::c_int) -> io::Result<()> {
+    loop {
+        if f() != -1 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+// Reap `pid` while `reaped` is locked
+fn wait(pid: libc::pid_t, reaped: &Mutex<bool>) -> io::Result<Usage> {
+    // Wait for the exit without reaping not to block `kill` meanwhile
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    retry(|| unsafe {
+        libc::waitid(
+            libc::P_PID,
+            pid as libc::id_t,
+            &mut info,
+            libc::WEXITED | libc::WNOWAIT,
+        )
+    })?;
+
+    let mut reaped = reaped.lock().unwrap();
+    let mut status = 0;
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    retry(|| unsafe { libc::wait4(pid, &mut status, 0, &mut rusage) })?;
+    *reaped = true;
+    // ru_maxrss is in kilobytes on Linux and in bytes on macOS
+    let memory = if cfg!(target_os = "macos") {
+        rusage.ru_maxrss as u64
+    } else {
+        rusage.ru_maxrss as u64 * 1024
+    };
+    Ok(Usage {
+        status: process::ExitStatus::from_raw(status),
+        memory: Some(memory),
+    })
+}
+
+// Allocation failure messages of Rust and C++
+fn is_allocation_failure(stderr: &str) -> bool {
+    stderr.contains("memory allocation of") || stderr.contains("bad_alloc")
+}
+
+// MLE or TLE by the limits of `command`.
+// RLIMIT_AS keeps the peak RSS under the memory limit and makes allocations fail instead. So
+// failures with an allocation failure message and crashes with the peak RSS near the limit, like
+// a C program using NULL from malloc, are judged MLE too. Crashes after a failed allocation
+// which used little memory are RE.
+pub fn limit_verdict(usage: &Usage, limits: &Limits, stderr: &str) -> Option<Verdict> {
+    let (exceeds_memory, near_memory) = match (usage.memory, limits.memory) {
+        (Some(used), Some(limit)) => (used > limit, used >= limit / 10 * 9),
+        _ => (false, false),
+    };
+    let fails_allocation = !usage.status.success()
+        && limits.memory.is_some()
+        && (is_allocation_failure(stderr) || (usage.status.signal().is_some() && near_memory));
+    if exceeds_memory || fails_allocation {
+        Some(Verdict::MLE)
+    } else if usage.status.signal() == Some(libc::SIGXCPU) {
+        Some(Verdict::TLE)
+    } else {
+        None
+    }
+}
+
+pub fn test_dir(path: &Path) -> Option<PathBuf> {
+    let mut name = path.file_stem()?.to_os_string();
+    name.push(".tests");
+    Some(path.with_file_name(name))
+}
+
+// The binary built by the compiler like `./a`
+pub fn program(path: &Path) -> Option<PathBuf> {
+    let mut prog = OsString::from("./");
+    prog.push(path.file_stem()?);
+    Some(PathBuf::from(prog))
+}
+
+// Cases in the order of their numbers
+pub fn list(dir: &Path) -> Vec<TestCase> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .map(|e| e.path())
+                .filter(|p| p.extension() == Some(OsStr::new("in")))
+                .filter_map(|p| p.file_stem()?.to_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort_by_key(|name| (name.parse::<usize>().unwrap_or(usize::MAX), name.clone()));
+    names.into_iter().map(|name| case(dir, name)).collect()
+}
+
+fn case(dir: &Path, name: String) -> TestCase {
+    TestCase {
+        input: dir.join(format!("{}.in", name)),
+        output: dir.join(format!("{}.out", name)),
+        name,
+    }
+}
+
+// A new empty case numbered after the others
+pub fn add(dir: &Path) -> io::Result<TestCase> {
+    fs::create_dir_all(dir)?;
+    let next = list(dir)
+        .iter()
+        .filter_map(|c| c.name.parse::<usize>().ok())
+        .max()
+        .unwrap_or(0)
+        + 1;
+    let case = case(dir, next.to_string());
+    fs::File::create(&case.input)?;
+    fs::File::create(&case.output)?;
+    Ok(case)
+}
+
+pub fn remove(case: &TestCase) -> io::Result<()> {
+    fs::remove_file(&case.input)?;
+    if case.output.exists() {
+        fs::remove_file(&case.output)?;
+    }
+    Ok(())
+}
+
+fn read_in_thread<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        String::from_utf8_lossy(&buf).into_owned()
+    })
+}
+
+// Lines without trailing spaces and blank lines at the end
+fn normalize(s: &str) -> Vec<&str> {
+    let mut lines: Vec<&str> = s.lines().map(str::trim_end).collect();
+    while lines.last() == Some(&"") {
+        lines.pop();
+    }
+    lines
+}
+
+// The first line where the outputs differ
+pub fn first_mismatch(output: &str, expected: &str, compare: Compare) -> Option<usize> {
+    if compare.is_same(output, expected) {
+        return None;
+    }
+    let a: Vec<&str> = output.lines().collect();
+    let b: Vec<&str> = expected.lines().collect();
+    let len = max(a.len(), b.len());
+    (0..len)
+        .find(|&i| !compare.is_same_line(a.get(i).unwrap_or(&""), b.get(i).unwrap_or(&"")))
+        // Differ only by line ends
+        .or_else(|| Some(len.saturating_sub(1)))
+}
+
+// Tokens with their char ranges
+fn tokens(line: &str) -> Vec<(Range<usize>, &str)> {
+    let mut res = Vec::new();
+    let mut start = None;
+    let chars = line
+        .char_indices()
+        .chain(std::iter::once((line.len(), ' ')));
+    for (col, (i, c)) in chars.enumerate() {
+        match (start, c.is_whitespace()) {
+            (None, false) => start = Some((col, i)),
+            (Some((l, j)), true) => {
+                res.push((l..col, &line[j..i]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    res
+}
+
+// Chars to highlight in lines `a` and `b`. Tokens differ at the same position are marked.
+// Whitespace is marked if the lines differ only by it.
+pub fn diff_line(a: &str, b: &str, compare: Compare) -> (Vec<bool>, Vec<bool>) {
+    let mut marks = (
+        vec![false; a.chars().count()],
+        vec![false; b.chars().count()],
+    );
+    if compare.is_same_line(a, b) {
+        return marks;
+    }
+    let (ta, tb) = (tokens(a), tokens(b));
+    for k in 0..max(ta.len(), tb.len()) {
+        if ta.get(k).map(|t| t.1) != tb.get(k).map(|t| t.1) {
+            if let Some((range, _)) = ta.get(k) {
+                marks.0[range.clone()].iter_mut().for_each(|m| *m = true);
+            }
+            if let Some((range, _)) = tb.get(k) {
+                marks.1[range.clone()].iter_mut().for_each(|m| *m = true);
+            }
+        }
+    }
+    if !marks.0.contains(&true) && !marks.1.contains(&true) {
+        for (line, marks) in [(a, &mut marks.0), (b, &mut marks.1)].iter_mut() {
+            for (c, m) in line.chars().zip(marks.iter_mut()) {
+                *m = c.is_whitespace();
+            }
+        }
+    }
+    marks
+}
+
+// Output of a program
+pub struct Execution {
+    pub usage: Usage,
+    // Killed by the time limit
+    pub timed_out: bool,
+    pub time: Duration,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+// Run `command` made by `command()` with `input` under `limits`
+pub fn execute(
+    mut command: process::Command,
+    input: Vec<u8>,
+    limits: &Limits,
+) -> io::Result<Execution> {
+    let start = Instant::now();
+    let mut child = command
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()?;
+    // Write and read in threads not to block the program by full pipes
+    if let Some(mut stdin) = child.stdin.take() {
+        thread::spawn(move || stdin.write_all(&input));
+    }
+    let stdout = read_in_thread(child.stdout.take());
+    let stderr = read_in_thread(child.stderr.take());
+    let waiter = Waiter::new(child);
+
+    let usage = match waiter.recv_timeout(limits.time) {
+        Some(usage) => usage?,
+        None => {
+            waiter.kill();
+            let time = start.elapsed();
+            let usage = waiter.recv()?;
+            // Don't wait for outputs which may be kept open by children of the program
+            return Ok(Execution {
+                usage,
+                timed_out: true,
+                time,
+                stdout: String::new(),
+                stderr: String::new(),
+            });
+        }
+    };
+    Ok(Execution {
+        usage,
+        timed_out: false,
+        time: start.elapsed(),
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+impl Execution {
+    pub fn verdict(&self, limits: &Limits, expected: &str, compare: Compare) -> Verdict {
+        if self.timed_out {
+            return Verdict::TLE;
+        }
+        match limit_verdict(&self.usage, limits, &self.stderr) {
+            Some(verdict) => verdict,
+            None if !self.usage.status.success() => Verdict::RE,
+            None if compare.is_same(&self.stdout, expected) => Verdict::AC,
+            None => Verdict::WA,
+        }
+    }
+}
+
+pub fn run(
+    program: &Path,
+    case: &TestCase,
+    limits: &Limits,
+    compare: Compare,
+) -> io::Result<TestResult> {
+    let input = fs::read(&case.input)?;
+    let expected = fs::read_to_string(&case.output).unwrap_or_default();
+    let execution = execute(command(program, limits), input, limits)?;
+    Ok(TestResult {
+        verdict: execution.verdict(limits, &expected, compare),
+        time: execution.time,
+        memory: execution.usage.memory,
+        stdout: execution.stdout,
+        stderr: execution.stderr,
+        expected,
+    })
+}
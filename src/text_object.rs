@@ -1,10 +1,33 @@
+use std::cmp::min;
+
+use regex::Regex;
+
 use crate::core::{Core, Cursor, CursorRange};
+use crate::ropey_util::RopeExt;
 
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub enum Action {
     Delete,
     Yank,
     Change,
+    // >
+    ShiftRight,
+    // <
+    ShiftLeft,
+    // gu
+    Lowercase,
+    // gU
+    Uppercase,
+    // g~
+    ToggleCase,
+    // =
+    Reindent,
+    // gq
+    Rewrap,
+    // gc
+    Comment,
+    // !
+    Filter,
 }
 
 impl Action {
@@ -13,15 +36,58 @@ impl Action {
             'd' => Some(Action::Delete),
             'y' => Some(Action::Yank),
             'c' => Some(Action::Change),
+            '>' => Some(Action::ShiftRight),
+            '<' => Some(Action::ShiftLeft),
+            '=' => Some(Action::Reindent),
+            '!' => Some(Action::Filter),
+            _ => None,
+        }
+    }
+
+    // Operators after `g`
+    pub fn from_g_char(c: char) -> Option<Self> {
+        match c {
+            'u' => Some(Action::Lowercase),
+            'U' => Some(Action::Uppercase),
+            '~' => Some(Action::ToggleCase),
+            'q' => Some(Action::Rewrap),
+            'c' => Some(Action::Comment),
             _ => None,
         }
     }
 
+    // The last key of the operator. Repeating it like `>>` or `guu` operates on lines.
     pub fn to_char(self) -> char {
         match self {
             Action::Delete => 'd',
             Action::Yank => 'y',
             Action::Change => 'c',
+            Action::ShiftRight => '>',
+            Action::ShiftLeft => '<',
+            Action::Lowercase => 'u',
+            Action::Uppercase => 'U',
+            Action::ToggleCase => '~',
+            Action::Reindent => '=',
+            Action::Rewrap => 'q',
+            Action::Comment => 'c',
+            Action::Filter => '!',
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Delete => "Delete",
+            Action::Yank => "Yank",
+            Action::Change => "Change",
+            Action::ShiftRight => "Shift right",
+            Action::ShiftLeft => "Shift left",
+            Action::Lowercase => "Lowercase",
+            Action::Uppercase => "Uppercase",
+            Action::ToggleCase => "Toggle case",
+            Action::Reindent => "Reindent",
+            Action::Rewrap => "Rewrap",
+            Action::Comment => "Comment",
+            Action::Filter => "Filter",
         }
     }
 }
@@ -50,10 +116,100 @@ pub trait TextObject {
 
 struct Word;
 
+// Non-blank characters
+struct BigWord;
+
 struct Quote(char);
 
 struct Parens(char, char);
 
+// An argument between commas in brackets
+struct Argument;
+
+// Lines indented at least as deep as the cursor line
+struct Indent;
+
+// Lines separated by blank lines
+struct Paragraph;
+
+// Ends with `.`, `!` or `?` followed by a space
+struct Sentence;
+
+// XML / HTML element
+struct Tag;
+
+// Range which covers whole lines from `l` to `r` without the last line end
+pub fn lines_range(core: &Core, l: usize, r: usize) -> Option<CursorRange> {
+    let end = core.prev_cursor(Cursor {
+        row: r,
+        col: core.buffer().l(r).len_chars(),
+    })?;
+    let start = Cursor { row: l, col: 0 };
+    if start <= end {
+        Some(CursorRange(start, end))
+    } else {
+        None
+    }
+}
+
+// Linewise text objects. Change keeps a line to insert.
+fn linewise_range(action: Action, core: &Core, l: usize, r: usize) -> Option<CursorRange> {
+    if action == Action::Change {
+        return lines_range(core, l, r);
+    }
+    let last = core.buffer().len_lines() - 1;
+    let end = Cursor {
+        row: r,
+        col: core.buffer().l(r).len_chars(),
+    };
+    if r == last && l > 0 && action == Action::Delete {
+        // Remove the line end before the lines instead
+        let start = Cursor {
+            row: l - 1,
+            col: core.buffer().l(l - 1).len_chars(),
+        };
+        Some(CursorRange(start, end))
+    } else {
+        Some(CursorRange(Cursor { row: l, col: 0 }, end))
+    }
+}
+
+// Characters of the lines with their positions. Line ends are `\n`.
+fn chars_with_cursor(core: &Core, rows: std::ops::Range<usize>) -> Vec<(Cursor, char)> {
+    let mut res = Vec::new();
+    let last = core.buffer().len_lines() - 1;
+    for row in rows {
+        let line = core.buffer().l(row);
+        res.extend(
+            line.chars()
+                .enumerate()
+                .map(|(col, c)| (Cursor { row, col }, c)),
+        );
+        if row < last {
+            res.push((
+                Cursor {
+                    row,
+                    col: line.len_chars(),
+                },
+                '\n',
+            ));
+        }
+    }
+    res
+}
+
+fn is_blank_line(core: &Core, row: usize) -> bool {
+    core.buffer().l(row).chars().all(char::is_whitespace)
+}
+
+fn indent_of(core: &Core, row: usize) -> usize {
+    core.buffer()
+        .l(row)
+        .chars()
+        .take_while(|&c| c == ' ' || c == '\t')
+        .count()
+}
+
 impl TextObject for Quote {
     fn get_range(&self, _: Action, prefix: TextObjectPrefix, core: &Core) -> Option<CursorRange> {
         match prefix {
@@ -186,9 +342,342 @@ impl TextObject for Word {
     }
 }
 
+impl TextObject for BigWord {
+    fn get_range(
+        &self,
+        action: Action,
+        prefix: TextObjectPrefix,
+        core: &Core,
+    ) -> Option<CursorRange> {
+        let pos = core.cursor();
+        let line = core.current_line();
+        let is_word = |i: usize| !line.char(i).is_whitespace();
+        if pos.col >= line.len_chars() {
+            return None;
+        }
+        let mut l = pos.col;
+        let mut r = pos.col;
+        if prefix != TextObjectPrefix::None {
+            while l > 0 && is_word(l - 1) {
+                l -= 1;
+            }
+        }
+        while r + 1 < line.len_chars() && is_word(r + 1) {
+            r += 1;
+        }
+        if action != Action::Change && prefix != TextObjectPrefix::Inner {
+            while r + 1 < line.len_chars() && line.char(r + 1) == ' ' {
+                r += 1;
+            }
+        }
+        Some(CursorRange(
+            Cursor {
+                row: pos.row,
+                col: l,
+            },
+            Cursor {
+                row: pos.row,
+                col: r,
+            },
+        ))
+    }
+}
+
+impl TextObject for Argument {
+    fn get_range(&self, _: Action, prefix: TextObjectPrefix, core: &Core) -> Option<CursorRange> {
+        if prefix == TextObjectPrefix::None {
+            return None;
+        }
+        let chars = chars_with_cursor(core, 0..core.buffer().len_lines());
+        let cursor = chars.iter().position(|&(c, _)| c == core.cursor())?;
+        let is_open = |c: char| c == '(' || c == '[' || c == '{';
+        let is_close = |c: char| c == ')' || c == ']' || c == '}';
+
+        // Separators before and after the argument
+        let mut depth = 0;
+        let mut l = None;
+        for i in (0..cursor).rev() {
+            let c = chars[i].1;
+            if is_close(c) {
+                depth += 1;
+            } else if is_open(c) {
+                if depth == 0 {
+                    l = Some(i);
+                    break;
+                }
+                depth -= 1;
+            } else if c == ',' && depth == 0 {
+                l = Some(i);
+                break;
+            }
+        }
+        let mut depth = 0;
+        let mut r = None;
+        for (i, &(_, c)) in chars.iter().enumerate().skip(cursor) {
+            if is_open(c) {
+                depth += 1;
+            } else if is_close(c) {
+                if depth == 0 {
+                    r = Some(i);
+                    break;
+                }
+                depth -= 1;
+            } else if c == ',' && depth == 0 {
+                r = Some(i);
+                break;
+            }
+        }
+        let (l, r) = (l?, r?);
+
+        // Without surrounding spaces
+        let mut start = l + 1;
+        while start < r && chars[start].1.is_whitespace() {
+            start += 1;
+        }
+        let mut end = r;
+        while end > start && chars[end - 1].1.is_whitespace() {
+            end -= 1;
+        }
+        if start == end {
+            return None;
+        }
+        let (start, end) = if prefix == TextObjectPrefix::Inner {
+            (start, end)
+        } else if chars[r].1 == ',' {
+            // With the comma and spaces after it
+            let mut end = r + 1;
+            while end < chars.len() && chars[end].1.is_whitespace() {
+                end += 1;
+            }
+            (start, end)
+        } else if chars[l].1 == ',' {
+            // The last argument takes the comma before it
+            (l, end)
+        } else {
+            (start, end)
+        };
+        Some(CursorRange(chars[start].0, chars[end - 1].0))
+    }
+}
+
+impl TextObject for Indent {
+    fn get_range(
+        &self,
+        action: Action,
+        prefix: TextObjectPrefix,
+        core: &Core,
+    ) -> Option<CursorRange> {
+        if prefix == TextObjectPrefix::None {
+            return None;
+        }
+        let len_lines = core.buffer().len_lines();
+        let row = core.cursor().row;
+        // A blank line belongs to the block below
+        let base = (row..len_lines).find(|&r| !is_blank_line(core, r))?;
+        let level = indent_of(core, base);
+        let in_block = |r: usize| is_blank_line(core, r) || indent_of(core, r) >= level;
+
+        let mut l = base;
+        while l > 0 && in_block(l - 1) {
+            l -= 1;
+        }
+        let mut r = base;
+        while r + 1 < len_lines && in_block(r + 1) {
+            r += 1;
+        }
+        while l < r && is_blank_line(core, l) {
+            l += 1;
+        }
+        while r > l && is_blank_line(core, r) {
+            r -= 1;
+        }
+        // `ai` takes the line above like the header of the block
+        if prefix == TextObjectPrefix::A && l > 0 {
+            l -= 1;
+        }
+        linewise_range(action, core, l, r)
+    }
+}
+
+impl TextObject for Paragraph {
+    fn get_range(
+        &self,
+        action: Action,
+        prefix: TextObjectPrefix,
+        core: &Core,
+    ) -> Option<CursorRange> {
+        if prefix == TextObjectPrefix::None {
+            return None;
+        }
+        let len_lines = core.buffer().len_lines();
+        let row = core.cursor().row;
+        let blank = is_blank_line(core, row);
+        let mut l = row;
+        while l > 0 && is_blank_line(core, l - 1) == blank {
+            l -= 1;
+        }
+        let mut r = row;
+        while r + 1 < len_lines && is_blank_line(core, r + 1) == blank {
+            r += 1;
+        }
+        // `ap` takes blank lines after the paragraph, or before it at the end
+        if prefix == TextObjectPrefix::A {
+            if r + 1 < len_lines {
+                r += 1;
+                while r + 1 < len_lines && is_blank_line(core, r + 1) != blank {
+                    r += 1;
+                }
+            } else {
+                while l > 0 && is_blank_line(core, l - 1) != blank {
+                    l -= 1;
+                }
+            }
+        }
+        linewise_range(action, core, l, r)
+    }
+}
+
+impl TextObject for Sentence {
+    fn get_range(&self, _: Action, prefix: TextObjectPrefix, core: &Core) -> Option<CursorRange> {
+        if prefix == TextObjectPrefix::None {
+            return None;
+        }
+        // Search in the paragraph
+        let len_lines = core.buffer().len_lines();
+        let row = core.cursor().row;
+        if is_blank_line(core, row) {
+            return None;
+        }
+        let mut top = row;
+        while top > 0 && !is_blank_line(core, top - 1) {
+            top -= 1;
+        }
+        let mut bottom = row;
+        while bottom + 1 < len_lines && !is_blank_line(core, bottom + 1) {
+            bottom += 1;
+        }
+        let chars = chars_with_cursor(core, top..bottom + 1);
+        let len = if bottom + 1 < len_lines {
+            // Without the last line end
+            chars.len() - 1
+        } else {
+            chars.len()
+        };
+        let cursor = chars.iter().position(|&(c, _)| c == core.cursor())?;
+
+        // Starts of sentences
+        let mut starts = vec![0];
+        let mut i = 0;
+        while i < len {
+            if ['.', '!', '?'].contains(&chars[i].1) {
+                let mut j = i + 1;
+                while j < len && [')', ']', '"', '\''].contains(&chars[j].1) {
+                    j += 1;
+                }
+                if j < len && chars[j].1.is_whitespace() {
+                    while j < len && chars[j].1.is_whitespace() {
+                        j += 1;
+                    }
+                    starts.push(j);
+                    i = j;
+                    continue;
+                }
+            }
+            i += 1;
+        }
+        starts.push(len);
+        let k = starts.iter().rposition(|&s| s <= cursor)?;
+        let (start, next) = (starts[k], starts[min(k + 1, starts.len() - 1)]);
+        let mut end = next;
+        while end > start && chars[end - 1].1.is_whitespace() {
+            end -= 1;
+        }
+        if end == start {
+            return None;
+        }
+        let (start, end) = if prefix == TextObjectPrefix::Inner {
+            (start, end)
+        } else if next > end {
+            // With spaces after the sentence
+            (start, next)
+        } else {
+            let mut start = start;
+            while start > 0 && chars[start - 1].1.is_whitespace() {
+                start -= 1;
+            }
+            (start, end)
+        };
+        Some(CursorRange(chars[start].0, chars[end - 1].0))
+    }
+}
+
+impl TextObject for Tag {
+    fn get_range(&self, _: Action, prefix: TextObjectPrefix, core: &Core) -> Option<CursorRange> {
+        if prefix == TextObjectPrefix::None {
+            return None;
+        }
+        let chars = chars_with_cursor(core, 0..core.buffer().len_lines());
+        let cursor = chars.iter().position(|&(c, _)| c == core.cursor())?;
+        let text: String = chars.iter().map(|&(_, c)| c).collect();
+        // Char index of each byte offset
+        let mut index = vec![0; text.len() + 1];
+        for (i, (b, _)) in text.char_indices().enumerate() {
+            index[b] = i;
+        }
+        index[text.len()] = chars.len();
+
+        let regex = Regex::new(r"<(/?)([A-Za-z][\w:.-]*)[^<>]*?(/?)>").unwrap();
+        // Open tags as (name, start, end)
+        let mut stack: Vec<(&str, usize, usize)> = Vec::new();
+        for m in regex.captures_iter(&text) {
+            let all = m.get(0).unwrap();
+            let (start, end) = (index[all.start()], index[all.end()]);
+            let name = m.get(2).unwrap().as_str();
+            if !m[3].is_empty() {
+                continue;
+            }
+            if m[1].is_empty() {
+                stack.push((name, start, end));
+                continue;
+            }
+            // Close the innermost element of the name
+            if let Some(k) = stack.iter().rposition(|&(n, _, _)| n == name) {
+                let (_, open_start, open_end) = stack[k];
+                stack.truncate(k);
+                // The first closed element around the cursor is the innermost
+                if open_start <= cursor && cursor < end {
+                    let (l, r) = if prefix == TextObjectPrefix::Inner {
+                        (open_end, start)
+                    } else {
+                        (open_start, end)
+                    };
+                    return if l < r {
+                        Some(CursorRange(chars[l].0, chars[r - 1].0))
+                    } else {
+                        None
+                    };
+                }
+            }
+        }
+        None
+    }
+}
+
+// The pair around the cursor named by `c` like `(`, `b` or `"` with its delimiters
+pub fn surrounding_range(c: char, core: &Core) -> Option<CursorRange> {
+    let (l, r) = crate::operator::surround_pair(c)?;
+    if l == r {
+        Quote(l).get_range(Action::Delete, TextObjectPrefix::A, core)
+    } else {
+        Parens(l, r).get_range(Action::Delete, TextObjectPrefix::A, core)
+    }
+}
+
 pub struct TextObjectParser {
     pub action: Action,
     pub prefix: Prefix,
+    // Used by `f` and `t` to find n-th character
+    pub count: usize,
 }
 
 impl TextObjectParser {
@@ -196,27 +685,29 @@ impl TextObjectParser {
         Self {
             action,
             prefix: Prefix::TextObjectPrefix(TextObjectPrefix::None),
+            count: 1,
         }
     }
 }
 
 impl TextObjectParser {
     pub fn parse(&mut self, c: char, core: &Core) -> Option<Option<CursorRange>> {
-        if let Prefix::TextObjectPrefix(_) = self.prefix {
+        // `a`, `i`, `f` and `t` after `a` or `i` are text objects like `ia`
+        if let Prefix::TextObjectPrefix(TextObjectPrefix::None) = self.prefix {
             match c {
                 'a' => {
                     self.prefix = Prefix::TextObjectPrefix(TextObjectPrefix::A);
+                    return None;
                 }
                 'i' => {
                     self.prefix = Prefix::TextObjectPrefix(TextObjectPrefix::Inner);
+                    return None;
                 }
                 'f' | 't' => {
-                    if let Prefix::TextObjectPrefix { .. } = self.prefix {
-                        self.prefix = Prefix::Find {
-                            inclusive: c == 'f',
-                        };
-                        return None;
-                    }
+                    self.prefix = Prefix::Find {
+                        inclusive: c == 'f',
+                    };
+                    return None;
                 }
                 _ => (),
             }
@@ -228,12 +719,21 @@ impl TextObjectParser {
                 let l = core.cursor();
                 let mut r = l;
                 let line = core.current_line();
-                while r.col < line.len_chars() && line.char(r.col) != find {
-                    r.col += 1;
-                }
+                let mut count = self.count;
+                loop {
+                    while r.col < line.len_chars() && line.char(r.col) != find {
+                        r.col += 1;
+                    }
 
-                if r.col == line.len_chars() {
-                    return Some(None);
+                    if r.col == line.len_chars() {
+                        return Some(None);
+                    }
+
+                    count -= 1;
+                    if count == 0 {
+                        break;
+                    }
+                    r.col += 1;
                 }
 
                 if !inclusive {
@@ -252,6 +752,12 @@ impl TextObjectParser {
             }
             Prefix::TextObjectPrefix(text_object_prefix) => match c {
                 'w' => Some(Word.get_range(self.action, text_object_prefix, core)),
+                'W' => Some(BigWord.get_range(self.action, text_object_prefix, core)),
+                'a' => Some(Argument.get_range(self.action, text_object_prefix, core)),
+                'i' => Some(Indent.get_range(self.action, text_object_prefix, core)),
+                'p' => Some(Paragraph.get_range(self.action, text_object_prefix, core)),
+                's' => Some(Sentence.get_range(self.action, text_object_prefix, core)),
+                't' => Some(Tag.get_range(self.action, text_object_prefix, core)),
                 '\'' | '"' => Some(Quote(c).get_range(self.action, text_object_prefix, core)),
                 '{' | '}' => {
                     Some(Parens('{', '}').get_range(self.action, text_object_prefix, core))
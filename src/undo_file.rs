@@ -0,0 +1,78 @@
+use std::fs;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::core::{Core, History};
+
+// Undo history and folds saved in `[cache_dir]/acc/undo` to restore the last session
+#[derive(Serialize, Deserialize)]
+struct UndoFile {
+    path: PathBuf,
+    // Hash of the saved content. The history is discarded if the file was changed by others.
+    hash: u64,
+    history: History,
+    // Closed folds
+    #[serde(default)]
+    folds: Vec<(usize, usize)>,
+}
+
+// FNV-1a. std's hasher is not guaranteed to be stable between releases.
+fn fnv1a<'a, I: IntoIterator<Item = &'a [u8]>>(chunks: I) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for chunk in chunks {
+        for &b in chunk {
+            hash ^= u64::from(b);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+fn content_hash(core: &Core) -> u64 {
+    fnv1a(core.buffer().chunks().map(str::as_bytes))
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn undo_file_path(path: &Path) -> Option<PathBuf> {
+    let path = absolute(path);
+    let key = fnv1a(Some(path.to_string_lossy().as_bytes()));
+    dirs::cache_dir().map(|mut p| {
+        p.push("acc");
+        p.push("undo");
+        p.push(format!("{:016x}.json", key));
+        p
+    })
+}
+
+pub fn save(path: &Path, core: &Core) -> Result<(), failure::Error> {
+    let undo_file_path = undo_file_path(path).ok_or_else(|| failure::err_msg("No cache dir"))?;
+    if let Some(parent) = undo_file_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let undo_file = UndoFile {
+        path: absolute(path),
+        hash: content_hash(core),
+        history: core.history(),
+        folds: core.folds(),
+    };
+    let f = fs::File::create(undo_file_path)?;
+    serde_json::to_writer(BufWriter::new(f), &undo_file)?;
+    Ok(())
+}
+
+// Restore the history if the content is the same as the last save
+pub fn load(path: &Path, core: &mut Core) -> Result<(), failure::Error> {
+    let undo_file_path = undo_file_path(path).ok_or_else(|| failure::err_msg("No cache dir"))?;
+    let f = fs::File::open(undo_file_path)?;
+    let undo_file: UndoFile = serde_json::from_reader(BufReader::new(f))?;
+    if undo_file.path == absolute(path) && undo_file.hash == content_hash(core) {
+        core.set_history(undo_file.history);
+        core.set_folds(&undo_file.folds);
+    }
+    Ok(())
+}
@@ -1,5 +1,6 @@
 use termion::event::{Event, Key};
 
+use accepted::buffer_tab::BufferTab;
 use accepted::{config, Buffer, BufferMode};
 
 trait BufferModeExt {
@@ -44,6 +45,25 @@ fn simple_test(init: &str, commands: &str, expected: &str) {
     });
 }
 
+// An executable shell script `name` in `dir`
+fn write_script(dir: &std::path::Path, name: &str, body: &str) -> std::path::PathBuf {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    let path = dir.join(name);
+    fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+// For the config of the language of `path`
+fn simple_test_with_path(path: &str, init: &str, commands: &str, expected: &str) {
+    with_buffer_mode_from(init, |mut state| {
+        state.buf.set_path(path.into());
+        state.command_esc(commands);
+        assert_eq!(state.buf.core.get_string(), expected);
+    });
+}
+
 #[test]
 fn test_simples() {
     // Insertions
@@ -109,3 +129,961 @@ fn test_simples() {
     simple_test("123", "A\n", "123\n");
     simple_test("123{", "A\n", "123{\n    ");
 }
+
+#[test]
+fn test_count() {
+    // Motions
+    simple_test("1\n2\n3\n4", "2jdd", "1\n2\n4");
+    simple_test("123 456 789", "2wi-", "123 456 -789");
+    simple_test("123 456 789", "2wx", "123 456 89");
+    simple_test("1\n2\n3\n4", "3Gdd", "1\n2\n4");
+
+    // Operators
+    simple_test("1\n2\n3\n4", "2dd", "3\n4");
+    simple_test("1\n2\n3\n4", "d2d", "3\n4");
+    simple_test("1\n2\n3\n4", "2dj", "4");
+    simple_test("1\n2\n3\n4\n5", "4G2dk", "1\n5");
+    simple_test("123 456 789", "2dw", "789");
+    simple_test("123 456 789", "d2w", "789");
+    simple_test("123 456 789", "2cwabc", "abc 789");
+    simple_test("1\n2\n3", "2ccabc", "abc\n3");
+    simple_test("123456123", "d2f3", "");
+    simple_test("123456", "3x", "456");
+    simple_test("123", "3r0", "000");
+    simple_test("123", "4r0", "123");
+
+    // Puts
+    simple_test("123", "yy2p", "123\n123\n123");
+
+    // Dot macro
+    simple_test("1 2 3 4 5", "dw2.", "4 5");
+    simple_test("1 2 3 4 5", "2dw.", "5");
+    // The count of `.` replaces the count of the motion
+    simple_test("a b c d e f g h", "d2w3.", "f g h");
+    simple_test("a b c d e f g h", "2d2w3.", "h");
+
+    // Blank lines at the end of lines yanked with a count
+    simple_test("a\n\nb", "2yyP", "a\n\na\n\nb");
+    simple_test("a\n\nb", "yjP", "a\n\na\n\nb");
+}
+
+#[test]
+fn test_registers() {
+    // Named registers survive deletes
+    simple_test("1\n2\n3", "\"ayyjdd\"ap", "1\n3\n1");
+    simple_test("1\n2\n3", "\"ayyj\"Ayy\"aP", "1\n1\n2\n2\n3");
+    // "0 holds the last yank
+    simple_test("1\n2\n3", "yyjdd\"0p", "1\n3\n1");
+    // Numbered registers hold deleted lines
+    simple_test("1\n2\n3", "dddd\"2p", "3\n1");
+    // Black hole
+    simple_test("1\n2\n3", "yyj\"_ddp", "1\n3\n1");
+    // Small delete
+    simple_test("123 456", "xw\"-P", "23 1456");
+    // Visual mode
+    simple_test("123 456", "v\"byw\"bP", "123 1456");
+    // Dot macro repeats the register
+    simple_test("1 2 3", "\"adw.\"aP", "2 3");
+}
+
+#[test]
+fn test_keyboard_macros() {
+    simple_test("1\n2\n3\n4", "qaddq@a", "3\n4");
+    simple_test("1\n2\n3\n4\n5", "qaddq@a@@", "4\n5");
+    simple_test("1\n2\n3\n4\n5", "qaddq2@a", "4\n5");
+    with_buffer_mode_from("a\nb\nc", |mut state| {
+        state.command_esc("qaA;");
+        state.command("jq2@a");
+        assert_eq!(state.buf.core.get_string(), "a;\nb;\nc;");
+    });
+    // Appending to a macro
+    simple_test("1 2 3 4", "qadwqqAdwq@a", "");
+}
+
+#[test]
+fn test_search() {
+    simple_test("abc Abc abc", "/abc\nx", "abc bc abc");
+    simple_test("abc Abc abc", "/abc\nnx", "abc Abc bc");
+    // Smart case
+    simple_test("abc Abc abc", "/Abc\nnx", "abc bc abc");
+    // Regex
+    simple_test("a1 b22 c333", "/\\d+\nnx", "a1 b2 c333");
+    simple_test("a1 b22 c333", "$/\\d+\nNNx", "a1 b2 c333");
+    // Wraps around
+    simple_test("x\ny\nx", "G/x\nx", "\ny\nx");
+    // Esc restores the cursor
+    with_buffer_mode_from("abc\nabc", |mut state| {
+        state.command_esc("/c");
+        state.command("x");
+        assert_eq!(state.buf.core.get_string(), "bc\nabc");
+    });
+}
+
+#[test]
+fn test_command_line() {
+    // :s
+    simple_test("foo foo\nfoo", ":%s/foo/bar/g\n", "bar bar\nbar");
+    simple_test("foo foo\nfoo", ":%s/foo/bar/\n", "bar foo\nbar");
+    simple_test("a\na\na", ":2s/a/b/\n", "a\nb\na");
+    simple_test("a\na\na", ":1,2s/a/b/\n", "b\nb\na");
+    simple_test("a\na\na", "j:.,$s/a/b/\n", "a\nb\nb");
+    simple_test("hello world", ":s/(\\w+) (\\w+)/\\2 \\1/\n", "world hello");
+    simple_test("foo", ":s#o#<&>#g\n", "f<o><o>");
+    simple_test("a,b", ":s/,/\\n/\n", "a\nb");
+    simple_test("a\na\na", "Vj:s/a/b/\n", "b\nb\na");
+    // A substitution is undone at once
+    simple_test("a a\na", ":%s/a/b/g\nu", "a a\na");
+    // :g
+    simple_test("1\nx2\n3\nx4", ":g/x/d\n", "1\n3");
+    simple_test("1\nx2\n3\nx4", ":v/x/d\n", "x2\nx4");
+    simple_test("1\nx2\n3\nx4", ":g/x/s/\\d/9/\n", "1\nx9\n3\nx9");
+    simple_test("1\nx2\n3\nx4", ":g/x/d\nu", "1\nx2\n3\nx4");
+    // :d
+    simple_test("a\nb\nc", "2:d\n", "c");
+    // :<line>
+    simple_test("ab\ncd", ":2\nx", "ab\nd");
+}
+
+#[test]
+fn test_history_serialization() {
+    with_buffer_mode_from("abc\ndef", |mut state| {
+        state.command_esc("xjdd");
+        state.command_esc("iHello");
+        let json = serde_json::to_string(&state.buf.core.history()).unwrap();
+        let content = state.buf.core.get_string();
+
+        with_buffer_mode_from(&content, |mut state| {
+            state
+                .buf
+                .core
+                .set_history(serde_json::from_str(&json).unwrap());
+            state.command("u");
+            assert_eq!(state.buf.core.get_string(), "bc\n");
+            state.command("uu");
+            assert_eq!(state.buf.core.get_string(), "abc\ndef");
+            state.command("U");
+            assert_eq!(state.buf.core.get_string(), "bc\ndef");
+        });
+    });
+}
+
+// Open `name` in a temp dir with `content` and save it after `commands`
+fn save_with_undo_file(name: &str, content: &str, commands: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join("accepted_test_undo_file");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, content).unwrap();
+    with_buffer_mode(|mut state| {
+        state.buf.open(path.clone());
+        state.command_esc(commands);
+        assert!(state.buf.save(false));
+    });
+    path
+}
+
+#[test]
+fn test_undo_file_restore() {
+    let path = save_with_undo_file("restore.txt", "abc", "xx");
+    with_buffer_mode(|mut state| {
+        state.buf.open(path);
+        assert_eq!(state.buf.core.get_string(), "c");
+        state.command("u");
+        assert_eq!(state.buf.core.get_string(), "bc");
+        state.command("u");
+        assert_eq!(state.buf.core.get_string(), "abc");
+    });
+}
+
+#[test]
+fn test_undo_file_changed() {
+    let path = save_with_undo_file("changed.txt", "abc", "x");
+    // Edited outside of the editor
+    std::fs::write(&path, "xyz").unwrap();
+    let mut empty = String::new();
+    with_buffer_mode_from("xyz", |state| {
+        empty = serde_json::to_string(&state.buf.core.history()).unwrap();
+    });
+    with_buffer_mode(|mut state| {
+        state.buf.open(path);
+        assert_eq!(
+            serde_json::to_string(&state.buf.core.history()).unwrap(),
+            empty
+        );
+        state.command("u");
+        assert_eq!(state.buf.core.get_string(), "xyz");
+    });
+}
+
+#[test]
+fn test_undo_tree() {
+    // Undo does not lose the branch of `x`
+    simple_test("abc", "xullxu", "abc");
+    simple_test("abc", "xullxuU", "ab");
+    // g- and g+ visit states in chronological order
+    simple_test("abc", "xullxg-", "bc");
+    simple_test("abc", "xullxg-g-", "abc");
+    simple_test("abc", "xullx2g-g+", "bc");
+    simple_test("abc", "xullxg-g-g+g+", "ab");
+    // :earlier and :later
+    simple_test("abc", "xullx:earlier 1m\n", "abc");
+    simple_test("abc", "xullx:earlier 1m\n:later 1h\n", "ab");
+    simple_test("abc", "xullx:earlier 2\n:later\n", "bc");
+    // gg
+    simple_test("ab\ncd\nef", "Gggx", "b\ncd\nef");
+    simple_test("ab\ncd\nef", "2ggx", "ab\nd\nef");
+}
+
+#[test]
+fn test_multiple_cursors() {
+    // A column of cursors
+    simple_test("abc\ndef\nghi", "lvjjI-", "a-bc\nd-ef\ng-hi");
+    // A cursor at the end of each line
+    simple_test("ab\nc", "VjA;", "ab;\nc;");
+    simple_test("ab\nc", "VjA(x", "ab(x)\nc(x)");
+    simple_test("a\nb", "VjA\nx", "a\nx\nb\nx");
+    with_buffer_mode_from("a\nb", |mut state| {
+        state.command_esc("VjI--");
+        state.command("ix");
+        assert_eq!(state.buf.core.get_string(), "--xa\n--xb");
+        // Edits at all cursors are undone at once
+        state.event(Event::Key(Key::Esc));
+        state.command("u");
+        assert_eq!(state.buf.core.get_string(), "--a\n--b");
+        // Esc in Normal mode removes other cursors
+        state.event(Event::Key(Key::Esc));
+        state.command("ggx");
+        assert_eq!(state.buf.core.get_string(), "-a\n--b");
+    });
+    // Add a cursor at the next match of the word under the cursor
+    with_buffer_mode_from("foo bar foo\nfoobar foo", |mut state| {
+        state.command("l");
+        state.event(Event::Key(Key::Ctrl('n')));
+        state.event(Event::Key(Key::Ctrl('n')));
+        // No more match
+        state.event(Event::Key(Key::Ctrl('n')));
+        state.command("x");
+        assert_eq!(state.buf.core.get_string(), "fo bar fo\nfoobar fo");
+    });
+    // Edits outside of Insert mode apply only at the main cursor
+    with_buffer_mode_from("foo foo\nbar", |mut state| {
+        state.event(Event::Key(Key::Ctrl('n')));
+        state.command(":2s/bar/baz/\n");
+        assert_eq!(state.buf.core.get_string(), "foo foo\nbaz");
+        state.command("ix");
+        assert_eq!(state.buf.core.get_string(), "foo foo\nxbaz");
+    });
+    with_buffer_mode_from("ab ab", |mut state| {
+        state.event(Event::Key(Key::Ctrl('n')));
+        state.command("yiwP");
+        assert_eq!(state.buf.core.get_string(), "ab abab");
+    });
+}
+
+#[test]
+fn test_operators() {
+    // Shift
+    simple_test("a\nb", ">>", "    a\nb");
+    simple_test("a\nb", "2>>", "    a\n    b");
+    simple_test("a\nb", ">j", "    a\n    b");
+    simple_test("      a", "<<", "  a");
+    simple_test("a\nb", ">>j.", "    a\n    b");
+    // Case
+    simple_test("ABC DEF", "guw", "abc DEF");
+    simple_test("abc def", "wgUiw", "abc DEF");
+    simple_test("aBc\nd", "g~~j.", "AbC\nD");
+    // Reindent
+    simple_test("{\nx\n  }", "=2j", "{\n    x\n}");
+    simple_test("{\n  x\n}", "j==", "{\n    x\n}");
+    // Rewrap
+    simple_test("a b\nc\n\nd", "gq3j", "a b c\n\nd");
+    let long = ["aaaa"; 20].join(" ");
+    let wrapped = format!("{}\n{}", ["aaaa"; 16].join(" "), ["aaaa"; 4].join(" "));
+    simple_test(&long, "gqq", &wrapped);
+    // Filter through a command
+    simple_test("b\na\nc", "!jsort\n", "a\nb\nc");
+    // A hanging command is killed
+    let start = std::time::Instant::now();
+    simple_test("a", "!!sleep 30\n", "a");
+    assert!(start.elapsed() < std::time::Duration::from_secs(30));
+}
+
+#[test]
+fn test_text_objects() {
+    // Arguments
+    simple_test("f(a, b, c)", "fbciax", "f(a, x, c)");
+    simple_test("f(a, g(b, c), d)", "fgdaa", "f(a, d)");
+    simple_test("f(a, b)", "fbdaa", "f(a)");
+    simple_test("f(a, [1, 2])", "f1daa", "f(a, [2])");
+    // Indentation blocks
+    simple_test("a\n  b\n\n  c\nd", "jdii", "a\nd");
+    simple_test("a\n  b\n  c\nd", "jdai", "d");
+    // Paragraphs
+    simple_test("a\nb\n\nc", "dap", "c");
+    simple_test("a\nb\n\nc", "dip", "\nc");
+    simple_test("a\nb\n\nc", "Gdap", "a\nb");
+    simple_test("a\nb\n\nc", "cipx", "x\n\nc");
+    simple_test("a\nb\n\nc", ">ip", "    a\n    b\n\nc");
+    // Sentences
+    simple_test("Foo bar. Baz qux. End", "fBdis", "Foo bar.  End");
+    simple_test("Foo bar. Baz qux. End", "fBdas", "Foo bar. End");
+    simple_test("Foo bar. Baz qux.", "fBdas", "Foo bar.");
+    // WORDs
+    simple_test("a.b c", "dW", "c");
+    simple_test("a.b c", "lciWx", "x c");
+    // Tags
+    simple_test("<a><b>x</b></a>", "fxdit", "<a><b></b></a>");
+    simple_test("<a><b>x</b></a>", "fxdat", "<a></a>");
+    simple_test(
+        "<a href=\"/\">\n  x\n</a>",
+        "jgUit",
+        "<a href=\"/\">\n  X\n</a>",
+    );
+}
+
+fn block_test(init: &str, before: &str, after: &str, expected: &str) {
+    with_buffer_mode_from(init, |mut state| {
+        state.command(before);
+        state.event(Event::Key(Key::Ctrl('v')));
+        state.command_esc(after);
+        assert_eq!(state.buf.core.get_string(), expected);
+    });
+}
+
+#[test]
+fn test_visual_block() {
+    block_test("abcd\nefgh\nijkl", "l", "ljd", "ad\neh\nijkl");
+    // Short lines are skipped
+    block_test("abcd\ne\nijkl", "ll", "jjld", "abd\ne\nijl");
+    // Insert on every line of the block
+    block_test("abc\ndef\nghi", "l", "jjI-", "a-bc\nd-ef\ng-hi");
+    block_test("abc\nd\nghi", "l", "jjA|", "ab|c\nd |\ngh|i");
+    block_test("abc\ndef", "", "ljs-", "-c\n-f");
+    // Put a block at the column of the cursor
+    block_test("ab\ncd\nef", "", "jyllp", "aba\ncdc\nef");
+    // Lines are added at the end
+    block_test("ab\ncd", "", "jyjp", "ab\ncad\n c");
+    block_test("abc\ndef", "l", "jdP", "abc\ndef");
+}
+
+#[test]
+fn test_marks() {
+    with_buffer_mode_from("aa\nbb\ncc", |mut state| {
+        state.command("jlma");
+        // Lines inserted or deleted above move marks
+        state.command_esc("ggOx");
+        state.command("`ax");
+        assert_eq!(state.buf.core.get_string(), "x\naa\nb\ncc");
+        state.command("ggdd'ax");
+        assert_eq!(state.buf.core.get_string(), "aa\n\ncc");
+        // Marks in Ex ranges
+        state.command("Gmagg");
+        state.event(Event::Key(Key::Char(':')));
+        state.command("'a,'ad\n");
+        assert_eq!(state.buf.core.get_string(), "aa\n");
+    });
+    // Jump list
+    with_buffer_mode_from("a\nb\nc\nd", |mut state| {
+        state.command("jG");
+        state.command("3gg");
+        state.event(Event::Key(Key::Ctrl('o')));
+        assert_eq!(state.buf.core.cursor().row, 3);
+        state.event(Event::Key(Key::Ctrl('o')));
+        assert_eq!(state.buf.core.cursor().row, 1);
+        // Ctrl-i
+        state.command("\t");
+        assert_eq!(state.buf.core.cursor().row, 3);
+        state.command("\t");
+        assert_eq!(state.buf.core.cursor().row, 2);
+    });
+    // Global marks across tabs
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::ConfigWithDefault::default();
+    let mut tab = BufferTab::new(&syntax_parent, &config);
+    let command = |tab: &mut BufferTab, command: &str| {
+        for c in command.chars() {
+            tab.event(Event::Key(Key::Char(c)));
+        }
+    };
+    command(&mut tab, "ia\nb");
+    tab.event(Event::Key(Key::Esc));
+    // Set `A` and open a new tab
+    command(&mut tab, "kmA c");
+    assert_eq!(tab.buffer_mode().buf.core.get_string(), "");
+    command(&mut tab, "`A");
+    assert_eq!(tab.buffer_mode().buf.core.get_string(), "a\nb");
+    assert_eq!(tab.buffer_mode().buf.core.cursor().row, 0);
+}
+
+#[test]
+fn test_matching_bracket() {
+    simple_test("(a [b] c)", "%x", "(a [b] c");
+    simple_test("(a [b] c)", "$h%x", "a [b] c)");
+    simple_test("(a [b] c)", "lld%", "(a c)");
+    simple_test("f(a,\n  b) + 1", "d%", " + 1");
+    simple_test("(a [b] c)", "lllv%d", "(a  c)");
+    // Brackets in strings and comments are skipped
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::ConfigWithDefault::default();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.core.set_string(r#"foo("(", [1]) # )"#.into(), true);
+    buf.set_path("a.rb".into());
+    let mut state = BufferMode::new(buf);
+    state.command("%");
+    assert_eq!(state.buf.core.cursor().col, 12);
+    state.command_esc("0d%");
+    assert_eq!(state.buf.core.get_string(), " # )");
+}
+
+#[test]
+fn test_folds() {
+    let init = "fn a() {\n    if x {\n        1\n    }\n}\n\nb\n    c";
+    with_buffer_mode_from(init, |mut state| {
+        // Bracket folds include the closing line
+        state.command("jjzc");
+        assert_eq!(state.buf.core.fold(2), Some((1, 3)));
+        assert_eq!(state.buf.core.cursor().row, 1);
+        state.command("j");
+        assert_eq!(state.buf.core.cursor().row, 4);
+        state.command("k");
+        assert_eq!(state.buf.core.cursor().row, 1);
+        // Outer fold
+        state.command("kzc");
+        assert_eq!(state.buf.core.fold(2), Some((0, 4)));
+        state.command("jj");
+        assert_eq!(state.buf.core.cursor().row, 6);
+        state.command("kkzo");
+        assert_eq!(state.buf.core.fold(0), None);
+        assert_eq!(state.buf.core.fold(2), Some((1, 3)));
+        state.command("j");
+        assert_eq!(state.buf.core.cursor().row, 1);
+        state.command("za");
+        assert_eq!(state.buf.core.fold(1), None);
+        // Indent folds
+        state.command("zM");
+        assert_eq!(state.buf.core.folds(), vec![(0, 4), (6, 7)]);
+        state.command("zR");
+        assert_eq!(state.buf.core.folds(), vec![]);
+        // Edits move folds and open folds of edited lines
+        state.command("Gzcggo");
+        state.event(Event::Key(Key::Esc));
+        assert_eq!(state.buf.core.folds(), vec![(7, 8)]);
+        state.command("G0x");
+        assert_eq!(state.buf.core.folds(), vec![]);
+    });
+    // Other motions into a closed fold open it
+    with_buffer_mode_from("a {\n    b\n}\nc", |mut state| {
+        state.command("zc/b\n");
+        assert_eq!(state.buf.core.cursor().into_tuple(), (1, 4));
+        assert_eq!(state.buf.core.folds(), vec![]);
+        state.command("ggzc2G");
+        assert_eq!(state.buf.core.cursor().row, 1);
+        assert_eq!(state.buf.core.folds(), vec![]);
+        state.command("jmaggzc`a");
+        assert_eq!(state.buf.core.cursor().row, 2);
+        assert_eq!(state.buf.core.folds(), vec![]);
+        state.command("ggzcw");
+        assert_eq!(state.buf.core.cursor().into_tuple(), (0, 2));
+        assert_eq!(state.buf.core.folds(), vec![(0, 2)]);
+        state.command("w");
+        assert_eq!(state.buf.core.cursor().into_tuple(), (1, 4));
+        assert_eq!(state.buf.core.folds(), vec![]);
+    });
+}
+
+#[test]
+fn test_indent_rules() {
+    use accepted::config;
+    assert!(config::parse_config_with_default(
+        "[file.py]\nindent_rules = { increase = \"(\", decrease = \"^$\" }"
+    )
+    .is_err());
+    // Dedent by typing `}`
+    simple_test("if x {", "A\ny\n}", "if x {\n    y\n}");
+    // Tabs are indents
+    simple_test("\tif x {", "A\ny", "\tif x {\n        y");
+    simple_test_with_path(
+        "a.py",
+        "if x:",
+        "A\npass\nelse:\npass",
+        "if x:\n    pass\nelse:\n    pass",
+    );
+    simple_test_with_path(
+        "a.py",
+        "if x:\ny\nelse:\nz",
+        "=3j",
+        "if x:\n    y\nelse:\n    z",
+    );
+    simple_test_with_path(
+        "a.cpp",
+        "switch (x) {",
+        "A\ncase 1:\nx;\ncase 2:",
+        "switch (x) {\ncase 1:\n  x;\ncase 2:",
+    );
+}
+
+#[test]
+fn test_expand_tab() {
+    // Spaces by default
+    simple_test("x", "A\ty", "x   y");
+    simple_test_with_path("Makefile", "all:", "A\n\tcc", "all:\n\tcc");
+    simple_test_with_path("a.go", "func f() {", "A\nx\n}", "func f() {\n\tx\n}");
+    simple_test_with_path("a.go", "x\n  y", ">j", "\tx\n\t  y");
+    simple_test_with_path("a.go", "\t\tx", "<<", "\tx");
+}
+
+#[test]
+fn test_comment() {
+    simple_test_with_path(
+        "a.rs",
+        "fn f() {\n    x;\n}",
+        "jgcc",
+        "fn f() {\n    // x;\n}",
+    );
+    simple_test_with_path("a.rs", "    // x;", "gcc", "    x;");
+    // At the least indent and skipping blank lines
+    simple_test_with_path("a.py", "a\n\n  b", "gc2j", "# a\n\n#   b");
+    // Uncomment only if all lines are commented
+    simple_test_with_path("a.py", "# a\nb", "gcj", "# # a\n# b");
+    simple_test_with_path("a.py", "# a\n#b\nc", "Vjgc", "a\nb\nc");
+    // One undo step
+    simple_test_with_path("a.sh", "a\nb", "gcju", "a\nb");
+    // Repeat by .
+    simple_test_with_path("a.toml", "a\nb", "gccj.", "# a\n# b");
+    // Block comments for languages without line comments
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config =
+        config::parse_config_with_default("[file.css]\ncomment = { block = [\"/*\", \"*/\"] }")
+            .unwrap();
+    let mut buf = Buffer::new(&syntax_parent, &config);
+    buf.core.set_string("a {\n  b: c;".into(), true);
+    buf.set_path("a.css".into());
+    let mut state = BufferMode::new(buf);
+    state.command_esc("jgcc");
+    assert_eq!(state.buf.core.get_string(), "a {\n  /* b: c; */");
+    state.command_esc("gcc");
+    assert_eq!(state.buf.core.get_string(), "a {\n  b: c;");
+}
+
+#[test]
+fn test_surround() {
+    simple_test("f(a, b)", "fads(", "fa, b");
+    simple_test("(( a ))", "fads(", "(a)");
+    simple_test("( a )", "fads)", " a ");
+    simple_test("x 'a' y", "facs'\"", "x \"a\" y");
+    simple_test("[ a ]", "facs[{", "{ a }");
+    simple_test("{ a }", "facsB)", "( a )");
+    simple_test("a b", "ysiw(", "( a ) b");
+    simple_test("a b", "ysw)", "(a) b");
+    simple_test("  a b ", "yss]", "  [a b] ");
+    simple_test("a b c", "ysfb\"", "\"a b\" c");
+    simple_test("a b", "vS{", "{ a } b");
+    // Repeat by .
+    simple_test("(a)\n(b)", "ds)j.", "a\nb");
+    simple_test("a\nb", "ysiwbj.", "(a)\n(b)");
+    // Nothing to delete
+    simple_test("a", "ds(", "a");
+}
+
+#[test]
+fn test_auto_pairs() {
+    simple_test("", "i(a", "(a)");
+    simple_test("", "i\"a\"b", "\"a\"b");
+    // Quotes after a word
+    simple_test("", "idon't", "don't");
+    // Configured pairs
+    let syntax_parent = accepted::syntax::SyntaxParent::default();
+    let config = config::ConfigWithDefault::default();
+    let rust_buffer = |init: &str| {
+        let mut buf = Buffer::new(&syntax_parent, &config);
+        buf.core.set_string(init.into(), true);
+        buf.set_path("a.rs".into());
+        BufferMode::new(buf)
+    };
+    let type_in = |init: &str, commands: &str, expected: &str| {
+        let mut state = rust_buffer(init);
+        state.command_esc(commands);
+        assert_eq!(state.buf.core.get_string(), expected);
+    };
+    type_in("", "ilet c = 'a", "let c = 'a'");
+    // Not in strings and comments
+    type_in("\"ab\"", "lli(", "\"a(b\"");
+    type_in("x // a", "A(\"", "x // a(\"");
+    type_in("x(\"a\"", "A(", "x(\"a\"()");
+    // Lifetimes
+    type_in("", "ifn f<'a>(x: &'a str)", "fn f<'a>(x: &'a str)");
+    type_in("", "iT: 'a + 'b", "T: 'a + 'b");
+    // Backspace deletes the empty pair
+    let mut state = rust_buffer("");
+    state.command("i'");
+    state.event(Event::Key(Key::Backspace));
+    state.event(Event::Key(Key::Esc));
+    assert_eq!(state.buf.core.get_string(), "");
+}
+
+#[test]
+fn test_increment() {
+    let ctrl = |state: &mut BufferMode, c: char| state.event(Event::Key(Key::Ctrl(c)));
+    let increment = |init: &str, count: &str, c: char, expected: &str| {
+        with_buffer_mode_from(init, |mut state| {
+            state.command(count);
+            ctrl(&mut state, c);
+            assert_eq!(state.buf.core.get_string(), expected);
+        });
+    };
+    increment(
+        "const MOD: u64 = 998244353;",
+        "",
+        'a',
+        "const MOD: u64 = 998244354;",
+    );
+    increment("a[10]", "5", 'x', "a[5]");
+    increment("x = -1", "2", 'a', "x = 1");
+    increment("n-1", "", 'a', "n-2");
+    increment("0xff", "", 'a', "0x100");
+    increment("0x00FF", "", 'x', "0x00FE");
+    increment("0b0111", "", 'a', "0b1000");
+    increment("1_000_000usize", "", 'a', "1_000_001usize");
+    increment("9_999_i32", "", 'a', "10_000_i32");
+    increment("no number", "", 'a', "no number");
+    // Every line in Visual mode and a sequence by g Ctrl-a
+    with_buffer_mode_from("a[0]\na[0]\na[0]", |mut state| {
+        state.command("Vjj");
+        ctrl(&mut state, 'a');
+        assert_eq!(state.buf.core.get_string(), "a[1]\na[1]\na[1]");
+        state.command("Vjjg");
+        ctrl(&mut state, 'a');
+        assert_eq!(state.buf.core.get_string(), "a[2]\na[3]\na[4]");
+        // One undo step
+        state.command("u");
+        assert_eq!(state.buf.core.get_string(), "a[1]\na[1]\na[1]");
+    });
+}
+
+#[test]
+fn test_test_cases() {
+    use accepted::test_case::{self, Compare, Verdict};
+    use std::fs;
+    use std::path::Path;
+    use std::time::Duration;
+
+    let dir = std::env::temp_dir().join("accepted_test_test_cases");
+    let _ = fs::remove_dir_all(&dir);
+    let source = dir.join("a.rs");
+    let tests = test_case::test_dir(&source).unwrap();
+    assert_eq!(tests, dir.join("a.tests"));
+
+    for _ in 0..10 {
+        test_case::add(&tests).unwrap();
+    }
+    let cases = test_case::list(&tests);
+    assert_eq!(cases[9].name, "10");
+    for case in &cases[2..] {
+        test_case::remove(case).unwrap();
+    }
+    let cases = test_case::list(&tests);
+    assert_eq!(cases.len(), 2);
+    fs::write(&cases[0].input, "1 2\n").unwrap();
+    fs::write(&cases[0].output, "1 2  \n\n").unwrap();
+    fs::write(&cases[1].input, "1 2\n").unwrap();
+    fs::write(&cases[1].output, "3\n").unwrap();
+
+    let verdict = |program: &Path, i: usize| {
+        let limits = test_case::Limits {
+            time: Duration::from_millis(500),
+            ..Default::default()
+        };
+        test_case::run(program, &cases[i], &limits, Compare::default())
+            .unwrap()
+            .verdict
+    };
+    assert_eq!(verdict(Path::new("cat"), 0), Verdict::AC);
+    assert_eq!(verdict(Path::new("cat"), 1), Verdict::WA);
+    assert_eq!(verdict(Path::new("false"), 0), Verdict::RE);
+    let sleep = write_script(&dir, "sleep.sh", "sleep 5");
+    assert_eq!(verdict(&sleep, 0), Verdict::TLE);
+}
+
+#[test]
+fn test_compare_output() {
+    use accepted::test_case::{diff_line, first_mismatch, Compare};
+
+    let output = "1 2 \n3 4\n";
+    assert!(!Compare::Exact.is_same(output, "1 2\n3 4\n"));
+    assert!(Compare::TrailingSpace.is_same(output, "1 2\n3 4\n\n"));
+    assert!(!Compare::TrailingSpace.is_same(output, "1 2\n3  4\n"));
+    assert!(Compare::Tokens.is_same(output, "1\n2 3\t4"));
+
+    assert_eq!(
+        first_mismatch(output, "1 2\n3 4\n", Compare::Exact),
+        Some(0)
+    );
+    assert_eq!(
+        first_mismatch(output, "1 2\n3 4\n", Compare::TrailingSpace),
+        None
+    );
+    assert_eq!(
+        first_mismatch(output, "1 2\n3 5\n", Compare::TrailingSpace),
+        Some(1)
+    );
+    assert_eq!(
+        first_mismatch(output, "1 2\n3 4\n5\n", Compare::Tokens),
+        Some(2)
+    );
+    assert_eq!(first_mismatch("1\n2", "1\n2\n", Compare::Exact), Some(1));
+
+    let t = true;
+    let f = false;
+    assert_eq!(
+        diff_line("10 20 30", "10 21 30 4", Compare::Exact),
+        (
+            vec![f, f, f, t, t, f, f, f],
+            vec![f, f, f, t, t, f, f, f, f, t]
+        )
+    );
+    assert_eq!(
+        diff_line("1  2", "1 2", Compare::Exact),
+        (vec![f, t, t, f], vec![f, t, f])
+    );
+    assert_eq!(
+        diff_line("1  2", "1 2", Compare::Tokens),
+        (vec![f; 4], vec![f; 3])
+    );
+}
+
+#[test]
+fn test_limits() {
+    use accepted::test_case::{self, Limits, Usage, Verdict};
+    use std::fs;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use std::time::Duration;
+
+    let dir = std::env::temp_dir().join("accepted_test_limits");
+    let _ = fs::remove_dir_all(&dir);
+    let tests = dir.join("a.tests");
+    let case = test_case::add(&tests).unwrap();
+
+    let limits = Limits::default().with_file(&tests);
+    assert_eq!(limits, Limits::default());
+    fs::write(
+        tests.join("limits.toml"),
+        "time_limit = 500\nmemory_limit = 0\n",
+    )
+    .unwrap();
+    let limits = Limits::default().with_file(&tests);
+    assert_eq!(limits.time, Duration::from_millis(500));
+    assert_eq!(limits.memory, None);
+
+    let usage = |status, memory| Usage {
+        status: ExitStatus::from_raw(status),
+        memory: Some(memory),
+    };
+    let limits = Limits {
+        time: Duration::from_secs(1),
+        memory: Some(64 << 20),
+    };
+    assert_eq!(
+        test_case::limit_verdict(&usage(0, 1 << 20), &limits, ""),
+        None
+    );
+    assert_eq!(
+        test_case::limit_verdict(&usage(0, 65 << 20), &limits, ""),
+        Some(Verdict::MLE)
+    );
+    assert_eq!(
+        test_case::limit_verdict(&usage(libc::SIGXCPU, 1 << 20), &limits, ""),
+        Some(Verdict::TLE)
+    );
+    // Crashes near the memory limit
+    assert_eq!(
+        test_case::limit_verdict(&usage(libc::SIGSEGV, 63 << 20), &limits, ""),
+        Some(Verdict::MLE)
+    );
+    assert_eq!(
+        test_case::limit_verdict(&usage(libc::SIGSEGV, 1 << 20), &limits, ""),
+        None
+    );
+
+    let script = write_script(
+        &dir,
+        "alloc.sh",
+        "echo 'memory allocation of 1048576 bytes failed' >&2\nexit 134",
+    );
+    let result = test_case::run(&script, &case, &limits, Default::default()).unwrap();
+    assert_eq!(result.verdict, Verdict::MLE);
+    assert!(result.memory.unwrap() > 0);
+
+    // A program writing to NULL returned by a failed allocation like C
+    let source = dir.join("alloc.rs");
+    fs::write(
+        &source,
+        "fn main() {
+            let layout = std::alloc::Layout::from_size_align(1 << 20, 1).unwrap();
+            loop {
+                let p = unsafe { std::alloc::alloc(layout) };
+                unsafe { std::ptr::write_bytes(std::hint::black_box(p), 1, 1 << 20) };
+            }
+        }",
+    )
+    .unwrap();
+    let program = dir.join("alloc");
+    let status = std::process::Command::new("rustc")
+        .arg("-O")
+        .arg(&source)
+        .arg("-o")
+        .arg(&program)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let result = test_case::run(&program, &case, &limits, Default::default()).unwrap();
+    assert_eq!(result.verdict, Verdict::MLE);
+
+    // Limits are set by setrlimit
+    let ulimit = |option: &str| {
+        let mut command = test_case::command(std::path::Path::new("sh"), &limits);
+        command.arg("-c").arg(format!("ulimit {}", option));
+        test_case::execute(command, Vec::new(), &limits)
+            .unwrap()
+            .stdout
+    };
+    assert_eq!(ulimit("-v"), format!("{}\n", (64 << 20) / 1024));
+    // The CPU time is limited a bit longer than the time limit
+    assert_eq!(ulimit("-t"), "3\n");
+}
+
+#[test]
+fn test_stress() {
+    use accepted::stress::{self, Programs, Progress};
+    use accepted::test_case::{Compare, Verdict};
+    use std::fs;
+    use std::path::Path;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::mpsc;
+
+    let dir = std::env::temp_dir().join("accepted_test_stress");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("a.rs");
+    assert_eq!(stress::generator(&source), Some(dir.join("a_gen.rs")));
+    assert_eq!(stress::brute(&source), Some(dir.join("a_brute.rs")));
+    assert!(!stress::is_built(&source));
+
+    let script = |name: &str, body: &str| write_script(&dir, name, body);
+    let programs = Programs {
+        generator: script("gen.sh", "echo $1"),
+        brute: Path::new("cat").to_path_buf(),
+        solution: script("sol.sh", "read n; [ $n = 3 ] && echo 4 || echo $n"),
+    };
+    let tests = dir.join("a.tests");
+    let (tx, rx) = mpsc::channel();
+    stress::run(
+        &programs,
+        &tests,
+        &Default::default(),
+        Compare::default(),
+        &AtomicBool::new(false),
+        &tx,
+    );
+    let progress: Vec<Progress> = rx.try_iter().collect();
+    assert_eq!(progress.len(), 3);
+    assert!(matches!(progress[1], Progress::Passed(2)));
+    match &progress[2] {
+        Progress::Found(3, Verdict::WA, case) => {
+            assert_eq!(fs::read_to_string(&case.input).unwrap(), "3\n");
+            assert_eq!(fs::read_to_string(&case.output).unwrap(), "3\n");
+        }
+        _ => panic!("Not found"),
+    }
+
+    let programs = Programs {
+        generator: script("gen.sh", "exit 1"),
+        ..programs
+    };
+    stress::run(
+        &programs,
+        &tests,
+        &Default::default(),
+        Compare::default(),
+        &AtomicBool::new(false),
+        &tx,
+    );
+    assert!(matches!(rx.try_recv(), Ok(Progress::Error(_))));
+}
+
+#[test]
+fn test_interactive() {
+    use accepted::interactive::{self, Transcript};
+    use accepted::test_case::{Limits, Verdict};
+    use std::fs;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let dir = std::env::temp_dir().join("accepted_test_interactive");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    assert_eq!(
+        interactive::interactor(&dir.join("a.rs")),
+        Some(dir.join("a_interactor.rs"))
+    );
+    let script = |name: &str, body: &str| write_script(&dir, name, body);
+    // Guess 3
+    let interactor = script(
+        "interactor.sh",
+        "while read g; do
+            if [ $g -lt 3 ]; then echo '<'; elif [ $g -gt 3 ]; then echo '>';
+            else echo '='; echo ok >&2; exit 0; fi
+        done
+        echo 'no answer' >&2
+        exit 1",
+    );
+    let limits = Limits {
+        time: Duration::from_millis(500),
+        ..Default::default()
+    };
+    let run = |solution: &str| {
+        let solution = script("solution.sh", solution);
+        let (tx, rx) = mpsc::channel();
+        let report = interactive::run(&solution, &interactor, &limits, &tx).unwrap();
+        let transcript: Vec<String> = rx
+            .try_iter()
+            .filter_map(|t| match t {
+                Transcript::Solution(s) => Some(format!("-> {}", s)),
+                Transcript::Interactor(s) => Some(format!("<- {}", s)),
+                _ => None,
+            })
+            .collect();
+        (report, transcript)
+    };
+
+    let (report, transcript) = run("i=1
+        while true; do echo $i; read r; [ \"$r\" = '=' ] && exit 0; i=$((i+1)); done");
+    assert_eq!(report.verdict, Verdict::AC);
+    assert_eq!(report.message, "ok");
+    assert_eq!(
+        transcript,
+        vec!["-> 1", "<- <", "-> 2", "<- <", "-> 3", "<- ="]
+    );
+
+    let (report, transcript) = run("echo 5; read r");
+    assert_eq!(report.verdict, Verdict::WA);
+    assert_eq!(report.message, "no answer");
+    assert_eq!(transcript, vec!["-> 5", "<- >"]);
+
+    let (report, _) = run("exec sleep 5");
+    assert_eq!(report.verdict, Verdict::TLE);
+
+    // Output without a line break reaches the interactor
+    let interactor = script(
+        "interactor.sh",
+        "g=$(head -c 1)
+        [ \"$g\" = 5 ] && echo ok >&2",
+    );
+    let solution = script("solution.sh", "printf 5; read r; exit 0");
+    let (tx, rx) = mpsc::channel();
+    let report = interactive::run(&solution, &interactor, &limits, &tx).unwrap();
+    assert_eq!(report.verdict, Verdict::AC);
+    assert_eq!(report.message, "ok");
+    assert!(rx
+        .try_iter()
+        .any(|t| matches!(t, Transcript::Solution(ref s) if s == "5")));
+}